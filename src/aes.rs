@@ -1,6 +1,7 @@
 use crate::fixed_tables::{FORWARD_SBOX, REVERSE_SBOX};
 use crate::util::{memset, SliceToHex};
 use std::slice;
+use std::sync::OnceLock;
 
 use crate::algorithms::{
     get_u32,
@@ -16,7 +17,7 @@ pub struct AesContext {
 }
 
 impl AesContext {
-    fn new() -> Self {
+    pub(crate) fn new() -> Self {
         AesContext {
             erk: [0u32; 64],
             drk: [0u32; 64],
@@ -86,7 +87,6 @@ const RCON: Rcon = [
 // decryption key schedule tables
 
 pub struct KeyTables {
-    pub init: bool,
     pub kt0: [u32; 256],
     pub kt1: [u32; 256],
     pub kt2: [u32; 256],
@@ -96,7 +96,6 @@ pub struct KeyTables {
 impl KeyTables {
     const fn new() -> KeyTables {
         KeyTables {
-            init: false,
             kt0: [0u32; 256],
             kt1: [0u32; 256],
             kt2: [0u32; 256],
@@ -156,19 +155,19 @@ pub fn gen_tables() -> ContextTables {
     // rsb[0x63] = 0x00;
 
     for i in 1..256 {
-        let mut x = pow[255 - log[i as usize] as usize];
+        let mut x = pow[255 - log[i] as usize];
 
         let mut y = x;
-        y = ( y << 1 ) | ( y >> 7 );
+        y = y.rotate_left(1);
 
         x ^= y;
-        y = ( y << 1 ) | ( y >> 7 );
+        y = y.rotate_left(1);
 
         x ^= y;
-        y = ( y << 1 ) | ( y >> 7 );
+        y = y.rotate_left(1);
 
         x ^= y;
-        y = ( y << 1 ) | ( y >> 7 );
+        y = y.rotate_left(1);
 
         x ^= y ^ 0x63;
 
@@ -180,7 +179,7 @@ pub fn gen_tables() -> ContextTables {
 
     let mul = |a,b| {
         if a != 0 && b != 0 {
-            pow[(log[a as usize].wrapping_add(log[b as usize])) as usize % 255] as u8
+            pow[(log[a as usize] as usize + log[b as usize] as usize) % 255]
         } else {
             0
         }
@@ -203,7 +202,7 @@ pub fn gen_tables() -> ContextTables {
 
         let y: u8 = rsb[i];
 
-        rt0[i] = ( (mul( 0x0B, y ) as u32)       ) ^
+        rt0[i] = (mul( 0x0B, y ) as u32) ^
                  ( (mul( 0x0D, y ) as u32) <<  8 ) ^
                  ( (mul( 0x09, y ) as u32) << 16 ) ^
                  ( (mul( 0x0E, y ) as u32) << 24 );
@@ -216,32 +215,47 @@ pub fn gen_tables() -> ContextTables {
     }
 
     let ft = ForwardTables {
-        fsb: fsb,
-        ft0: ft0,
-        ft1: ft1,
-        ft2: ft2,
-        ft3: ft3,
+        fsb,
+        ft0,
+        ft1,
+        ft2,
+        ft3,
     };
 
     let rt = ReverseTables {
-        rsb: rsb,
-        rt0: rt0,
-        rt1: rt1,
-        rt2: rt2,
-        rt3: rt3,
+        rsb,
+        rt0,
+        rt1,
+        rt2,
+        rt3,
     };
 
+    let mut kt = KeyTables::new();
+
+    for i in 0..256 {
+        kt.kt0[i] = rt.rt0[ ft.fsb[i] as usize ];
+        kt.kt1[i] = rt.rt1[ ft.fsb[i] as usize ];
+        kt.kt2[i] = rt.rt2[ ft.fsb[i] as usize ];
+        kt.kt3[i] = rt.rt3[ ft.fsb[i] as usize ];
+    }
+
     ContextTables {
-        ft: ft,
-        rt: rt,
+        ft,
+        rt,
         rc: rcon,
-        kt: KeyTables::new(),
+        kt,
     }
 }
 
+// the tables above, generated on first use and shared by every key
+fn shared_tables() -> &'static ContextTables {
+    static TABLES: OnceLock<ContextTables> = OnceLock::new();
+    TABLES.get_or_init(gen_tables)
+}
+
 // AES key scheduling routine
 
-pub fn set_key(context: &mut AesContext, tables: &mut ContextTables, key: &[u8], nbits: isize) {
+pub fn set_key(context: &mut AesContext, tables: &ContextTables, key: &[u8], nbits: isize) {
     match nbits {
         128 => { context.nr = 10; },
         192 => { context.nr = 12; },
@@ -249,7 +263,7 @@ pub fn set_key(context: &mut AesContext, tables: &mut ContextTables, key: &[u8],
         _ => (),
     }
 
-    let mut rk = context.erk;
+    let rk = &mut context.erk;
 
     for i in 0..(nbits as usize >> 5) {
         rk[i] = get_u32( key, i * 4 )
@@ -257,7 +271,7 @@ pub fn set_key(context: &mut AesContext, tables: &mut ContextTables, key: &[u8],
 
     // setup encryption round keys
 
-    let mut rk_ptr = rk.as_ptr() as *mut u32;
+    let mut rk_ptr = rk.as_mut_ptr();
 
     match nbits {
         128 => {
@@ -269,7 +283,7 @@ pub fn set_key(context: &mut AesContext, tables: &mut ContextTables, key: &[u8],
                     ((tables.ft.fsb[(temp_rk[3] >> 16) as u8 as usize] as u32) << 24) ^
                     ((tables.ft.fsb[(temp_rk[3] >>  8) as u8 as usize] as u32) << 16) ^
                     ((tables.ft.fsb[(temp_rk[3]      ) as u8 as usize] as u32) <<  8) ^
-                    ((tables.ft.fsb[(temp_rk[3] >> 24) as u8 as usize] as u32)      );
+                    (tables.ft.fsb[(temp_rk[3] >> 24) as u8 as usize] as u32);
 
                 temp_rk[5]  = temp_rk[1] ^ temp_rk[4];
                 temp_rk[6]  = temp_rk[2] ^ temp_rk[5];
@@ -287,7 +301,7 @@ pub fn set_key(context: &mut AesContext, tables: &mut ContextTables, key: &[u8],
                     ((tables.ft.fsb[(temp_rk[5] >> 16) as u8 as usize] as u32) << 24) ^
                     ((tables.ft.fsb[(temp_rk[5] >>  8) as u8 as usize] as u32) << 16) ^
                     ((tables.ft.fsb[(temp_rk[5]      ) as u8 as usize] as u32) <<  8) ^
-                    ((tables.ft.fsb[(temp_rk[5] >> 24) as u8 as usize] as u32)      );
+                    (tables.ft.fsb[(temp_rk[5] >> 24) as u8 as usize] as u32);
 
                 temp_rk[7]   = temp_rk[1] ^ temp_rk[6];
                 temp_rk[8]   = temp_rk[2] ^ temp_rk[7];
@@ -307,17 +321,17 @@ pub fn set_key(context: &mut AesContext, tables: &mut ContextTables, key: &[u8],
                     ((tables.ft.fsb[(temp_rk[7] >> 16) as u8 as usize] as u32) << 24) ^
                     ((tables.ft.fsb[(temp_rk[7] >>  8) as u8 as usize] as u32) << 16) ^
                     ((tables.ft.fsb[(temp_rk[7]      ) as u8 as usize] as u32) <<  8) ^
-                    ((tables.ft.fsb[(temp_rk[7] >> 24) as u8 as usize] as u32)      );
+                    (tables.ft.fsb[(temp_rk[7] >> 24) as u8 as usize] as u32);
 
                 temp_rk[9]   = temp_rk[1] ^ temp_rk[8];
                 temp_rk[10]  = temp_rk[2] ^ temp_rk[9];
                 temp_rk[11]  = temp_rk[3] ^ temp_rk[10];
 
-                temp_rk[12] = temp_rk[4] ^ tables.rc[i] ^
-                    ((tables.ft.fsb[(temp_rk[11] >> 16) as u8 as usize] as u32) << 24) ^
-                    ((tables.ft.fsb[(temp_rk[11] >>  8) as u8 as usize] as u32) << 16) ^
-                    ((tables.ft.fsb[(temp_rk[11]      ) as u8 as usize] as u32) <<  8) ^
-                    ((tables.ft.fsb[(temp_rk[11] >> 24) as u8 as usize] as u32)      );
+                temp_rk[12] = temp_rk[4] ^
+                    ((tables.ft.fsb[(temp_rk[11] >> 24) as u8 as usize] as u32) << 24) ^
+                    ((tables.ft.fsb[(temp_rk[11] >> 16) as u8 as usize] as u32) << 16) ^
+                    ((tables.ft.fsb[(temp_rk[11] >>  8) as u8 as usize] as u32) <<  8) ^
+                    (tables.ft.fsb[(temp_rk[11]      ) as u8 as usize] as u32);
 
                 temp_rk[13]  = temp_rk[5] ^ temp_rk[12];
                 temp_rk[14]  = temp_rk[6] ^ temp_rk[13];
@@ -331,62 +345,51 @@ pub fn set_key(context: &mut AesContext, tables: &mut ContextTables, key: &[u8],
 
     // setup decryption round keys
 
-    if tables.kt.init {
-        for i in 0..256 {
-            tables.kt.kt0[i] = tables.rt.rt0[ tables.ft.fsb[i] as usize ];
-            tables.kt.kt1[i] = tables.rt.rt1[ tables.ft.fsb[i] as usize ];
-            tables.kt.kt2[i] = tables.rt.rt2[ tables.ft.fsb[i] as usize ];
-            tables.kt.kt3[i] = tables.rt.rt3[ tables.ft.fsb[i] as usize ];
-        }
-
-        tables.kt.init = false
-    }
-
-    let sk = context.drk;
+    let sk = &mut context.drk;
 
-    let sk_ptr = sk.as_ptr() as *mut u32;
+    let mut sk_ptr = sk.as_mut_ptr();
 
     // equivelant to C's
-    //     *A++ == *B++
-    let ptr_cp_incr = |mut a: *mut u32, mut b: *mut u32| {
-        let ref_a: &mut u32 = unsafe { &mut *a };
-        let ref_b: &u32 = unsafe { &*b };
+    //     *A++ = *B++
+    let ptr_cp_incr = |a: &mut *mut u32, b: &mut *mut u32| {
+        unsafe {
+            **a = **b;
 
-        *ref_a = *ref_b;
-
-        a = unsafe { a.add(1) };
-        b = unsafe { b.add(1) };
+            *a = a.add(1);
+            *b = b.add(1);
+        }
     };
 
-    for _ in 0..4 { ptr_cp_incr(sk_ptr, rk_ptr); }
+    for _ in 0..4 { ptr_cp_incr(&mut sk_ptr, &mut rk_ptr); }
 
-    let sk_from_key_table_flip = |mut sk_ptr: *mut u32, mut rk_ptr: *mut u32| {
-        let ref_sk: &mut u32 = unsafe { &mut *sk_ptr };
-        let ref_rk: &u32 = unsafe { &*rk_ptr };
+    let sk_from_key_table_flip = |sk_ptr: &mut *mut u32, rk_ptr: &mut *mut u32| {
+        unsafe {
+            let ref_rk: u32 = **rk_ptr;
 
-        *ref_sk = tables.kt.kt0[ (*(ref_rk) >> 24) as u8 as usize ] ^
-                  tables.kt.kt1[ (*(ref_rk) >> 16) as u8 as usize ] ^
-                  tables.kt.kt2[ (*(ref_rk) >>  8) as u8 as usize ] ^
-                  tables.kt.kt3[ (*(ref_rk)      ) as u8 as usize ];
+            **sk_ptr = tables.kt.kt0[ (ref_rk >> 24) as u8 as usize ] ^
+                       tables.kt.kt1[ (ref_rk >> 16) as u8 as usize ] ^
+                       tables.kt.kt2[ (ref_rk >>  8) as u8 as usize ] ^
+                       tables.kt.kt3[ (ref_rk      ) as u8 as usize ];
 
-        sk_ptr = unsafe { sk_ptr.add(1) };
-        rk_ptr = unsafe { rk_ptr.add(1) };
+            *sk_ptr = sk_ptr.add(1);
+            *rk_ptr = rk_ptr.add(1);
+        }
     };
 
     for i in 1..context.nr {
         rk_ptr = unsafe { rk_ptr.sub(8) };
 
-        for _ in 0..4 { sk_from_key_table_flip(sk_ptr, rk_ptr); }
+        for _ in 0..4 { sk_from_key_table_flip(&mut sk_ptr, &mut rk_ptr); }
     }
 
     rk_ptr = unsafe { rk_ptr.sub(8) };
 
-    for _ in 0..4 { ptr_cp_incr(sk_ptr, rk_ptr); }
+    for _ in 0..4 { ptr_cp_incr(&mut sk_ptr, &mut rk_ptr); }
 }
 
 // AES 128-bit block encryption routine
 
-pub fn encrypt(context: &mut AesContext, tables: &mut ContextTables, input: [u8; 16], output: &mut [u8; 16]) {
+pub fn encrypt(context: &AesContext, tables: &ContextTables, input: [u8; 16], output: &mut [u8; 16]) {
     let rk = context.erk;
 
     let mut x0 = get_u32(&input,  0); x0 ^= rk[0];
@@ -394,7 +397,7 @@ pub fn encrypt(context: &mut AesContext, tables: &mut ContextTables, input: [u8;
     let mut x2 = get_u32(&input,  8); x2 ^= rk[2];
     let mut x3 = get_u32(&input, 12); x3 ^= rk[3];
 
-    let mut rk_ptr = rk.as_ptr() as *const u32;
+    let mut rk_ptr = rk.as_ptr();
 
     let mut remaining = 0;
 
@@ -490,7 +493,7 @@ pub fn encrypt(context: &mut AesContext, tables: &mut ContextTables, input: [u8;
 
 // AES 128-bit block decryption routine
 
-pub fn decrypt(context: &mut AesContext, tables: &mut ContextTables, input: [u8; 16], output: &mut [u8; 16]) {
+pub fn decrypt(context: &AesContext, tables: &ContextTables, input: [u8; 16], output: &mut [u8; 16]) {
     let rk = context.drk;
 
     let mut x0 = get_u32(&input,  0); x0 ^= rk[0];
@@ -498,7 +501,7 @@ pub fn decrypt(context: &mut AesContext, tables: &mut ContextTables, input: [u8;
     let mut x2 = get_u32(&input,  8); x2 ^= rk[2];
     let mut x3 = get_u32(&input, 12); x3 ^= rk[3];
 
-    let mut rk_ptr = rk.as_ptr() as *const u32;
+    let mut rk_ptr = rk.as_ptr();
 
     let mut remaining = 0;
 
@@ -593,6 +596,64 @@ pub fn decrypt(context: &mut AesContext, tables: &mut ContextTables, input: [u8;
 }
 
 
+// A keyed block cipher working on 16 octet blocks in place.
+
+pub trait BlockCipher {
+    // key length in octets
+    const KEY_SIZE: usize;
+
+    fn encrypt_block(&self, block: &mut [u8; 16]);
+    fn decrypt_block(&self, block: &mut [u8; 16]);
+}
+
+// round keys; the tables are shared
+struct Keyed {
+    ctx: AesContext,
+}
+
+impl Keyed {
+    fn new(key: &[u8], nbits: isize) -> Self {
+        let mut ctx = AesContext::new();
+
+        set_key(&mut ctx, shared_tables(), key, nbits);
+
+        Keyed { ctx }
+    }
+
+    fn encrypt_block(&self, block: &mut [u8; 16]) {
+        let input = *block;
+        encrypt(&self.ctx, shared_tables(), input, block);
+    }
+
+    fn decrypt_block(&self, block: &mut [u8; 16]) {
+        let input = *block;
+        decrypt(&self.ctx, shared_tables(), input, block);
+    }
+}
+
+macro_rules! aes_cipher {
+    ($name:ident, $size:expr) => {
+        pub struct $name(Keyed);
+
+        impl $name {
+            pub fn new(key: &[u8; $size]) -> Self {
+                $name(Keyed::new(key, $size * 8))
+            }
+        }
+
+        impl BlockCipher for $name {
+            const KEY_SIZE: usize = $size;
+
+            fn encrypt_block(&self, block: &mut [u8; 16]) { self.0.encrypt_block(block) }
+            fn decrypt_block(&self, block: &mut [u8; 16]) { self.0.decrypt_block(block) }
+        }
+    }
+}
+
+aes_cipher!(Aes128, 16);
+aes_cipher!(Aes192, 24);
+aes_cipher!(Aes256, 32);
+
 static AES_ENC_TEST: [[u8; 16]; 3] = [
     [ 0xA0, 0x43, 0x77, 0xAB, 0xE2, 0x59, 0xB0, 0xD0,
       0xB5, 0xBA, 0x2D, 0x40, 0xA5, 0x01, 0x97, 0x1B ],
@@ -613,7 +674,12 @@ static AES_DEC_TEST: [[u8; 16]; 3] = [
 
 #[test]
 fn c3_aes256_nk8_nk14() {
-    let plaintext = "00112233445566778899aabbccddeeff";
+    let plaintext: [u8; 16] = [
+        0x00,        0x11,        0x22,        0x33,
+        0x44,        0x55,        0x66,        0x77,
+        0x88,        0x99,        0xaa,        0xbb,
+        0xcc,        0xdd,        0xee,        0xff,
+    ];
     let mut buf = [0u8; 16];
     let key: [u8; 32] = [
         0x00,        0x01,        0x02,        0x03,
@@ -627,13 +693,13 @@ fn c3_aes256_nk8_nk14() {
     ];
 
     let mut ctx = AesContext::new();
-    let mut tables = gen_tables();
+    let tables = gen_tables();
 
-    set_key(&mut ctx, &mut tables, &key, 256);
+    set_key(&mut ctx, &tables, &key, 256);
 
-    encrypt(&mut ctx, &mut tables, buf, &mut buf);
+    encrypt(&ctx, &tables, plaintext, &mut buf);
 
-    assert_eq!(<[u8]>::slice_to_hex(&buf), "63");
+    assert_eq!(<[u8]>::slice_to_hex(&buf), "8ea2b7ca516745bfeafc49904b496089");
 }
 
 #[test]
@@ -642,17 +708,17 @@ fn test_encrypt() {
     let mut key = [0u8; 32];
 
     let mut ctx = AesContext::new();
-    let mut tables = gen_tables();
+    let tables = gen_tables();
 
     for n in 0..3 {
         memset(buf.as_ptr() as *mut u8, 0, 16);
         memset(key.as_ptr() as *mut u8, 0, 16 + n * 8);
 
         for i in 0..400 {
-            set_key(&mut ctx, &mut tables, &key, (128 + n * 64) as isize);
+            set_key(&mut ctx, &tables, &key, (128 + n * 64) as isize);
 
-            for j in 0..999 {
-                encrypt(&mut ctx, &mut tables, buf, &mut buf);
+            for j in 0..9999 {
+                encrypt(&ctx, &tables, buf, &mut buf);
             }
 
             if n > 0 {
@@ -665,7 +731,7 @@ fn test_encrypt() {
                 }
             }
 
-            encrypt(&mut ctx, &mut tables, buf, &mut buf);
+            encrypt(&ctx, &tables, buf, &mut buf);
 
             for j in 0..16 {
                 key[j + (n << 3)] ^= buf[j];
@@ -679,6 +745,264 @@ fn test_encrypt() {
 
 #[test]
 fn test_decrypt() {
+    let mut buf = [0u8; 16];
+    let mut key = [0u8; 32];
+
+    let mut ctx = AesContext::new();
+    let tables = gen_tables();
+
     for n in 0..3 {
+        memset(buf.as_ptr() as *mut u8, 0, 16);
+        memset(key.as_ptr() as *mut u8, 0, 16 + n * 8);
+
+        for i in 0..400 {
+            set_key(&mut ctx, &tables, &key, (128 + n * 64) as isize);
+
+            for j in 0..9999 {
+                decrypt(&ctx, &tables, buf, &mut buf);
+            }
+
+            if n > 0 {
+                let mut j = 0;
+                loop {
+                    if j >= (n << 3) { break; }
+
+                    key[j] ^= buf[j + 16 - (n << 3)];
+                    j += 1;
+                }
+            }
+
+            decrypt(&ctx, &tables, buf, &mut buf);
+
+            for j in 0..16 {
+                key[j + (n << 3)] ^= buf[j];
+            }
+        }
+        for i in 0..16 {
+            assert_eq!(buf[i], AES_DEC_TEST[n][i]);
+        }
+    }
+}
+
+// FIPS-197 Appendix C: key 000102..., plaintext 00112233...
+#[cfg(test)]
+fn fips197_appendix_c(nbits: isize, ciphertext: &str) {
+    let mut key = [0u8; 32];
+    let mut plaintext = [0u8; 16];
+
+    for i in 0..32 { key[i] = i as u8; }
+    for i in 0..16 { plaintext[i] = (i * 0x11) as u8; }
+
+    let mut ctx = AesContext::new();
+    let tables = gen_tables();
+
+    set_key(&mut ctx, &tables, &key[..nbits as usize / 8], nbits);
+
+    let mut buf = [0u8; 16];
+    encrypt(&ctx, &tables, plaintext, &mut buf);
+    assert_eq!(<[u8]>::slice_to_hex(&buf), ciphertext);
+
+    let mut out = [0u8; 16];
+    decrypt(&ctx, &tables, buf, &mut out);
+    assert_eq!(out, plaintext);
+}
+
+#[test]
+fn c1_aes128_inverse_cipher() {
+    fips197_appendix_c(128, "69c4e0d86a7b0430d8cdb78070b4c55a");
+}
+
+#[test]
+fn c2_aes192_inverse_cipher() {
+    fips197_appendix_c(192, "dda97ca4864cdfe06eaf70a0ec0d7191");
+}
+
+#[test]
+fn c3_aes256_inverse_cipher() {
+    fips197_appendix_c(256, "8ea2b7ca516745bfeafc49904b496089");
+}
+
+#[test]
+fn rekeyed_context() {
+    // a context reused for a shorter key must not keep the old schedule
+    let mut ctx = AesContext::new();
+    let tables = gen_tables();
+    let key = [0x2bu8; 32];
+
+    set_key(&mut ctx, &tables, &key, 256);
+    set_key(&mut ctx, &tables, &key[..16], 128);
+
+    let fresh = Aes128::new(&[0x2b; 16]);
+
+    let mut expected = [0x5au8; 16];
+    fresh.encrypt_block(&mut expected);
+
+    let mut buf = [0u8; 16];
+    encrypt(&ctx, &tables, [0x5a; 16], &mut buf);
+    assert_eq!(buf, expected);
+
+    let mut out = [0u8; 16];
+    decrypt(&ctx, &tables, buf, &mut out);
+    assert_eq!(out, [0x5a; 16]);
+}
+
+#[cfg(test)]
+fn round_trip<C: BlockCipher>(cipher: &C, expected: &str) {
+    let plaintext = [
+        0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77,
+        0x88, 0x99, 0xaa, 0xbb, 0xcc, 0xdd, 0xee, 0xff,
+    ];
+
+    let mut block = plaintext;
+    cipher.encrypt_block(&mut block);
+    assert_eq!(<[u8]>::slice_to_hex(&block), expected);
+
+    cipher.decrypt_block(&mut block);
+    assert_eq!(block, plaintext);
+}
+
+#[test]
+fn block_cipher_fips197_appendix_c() {
+    let mut key = [0u8; 32];
+    for i in 0..32 { key[i] = i as u8; }
+
+    let mut key128 = [0u8; 16];
+    let mut key192 = [0u8; 24];
+    key128.copy_from_slice(&key[..16]);
+    key192.copy_from_slice(&key[..24]);
+
+    round_trip(&Aes128::new(&key128), "69c4e0d86a7b0430d8cdb78070b4c55a");
+    round_trip(&Aes192::new(&key192), "dda97ca4864cdfe06eaf70a0ec0d7191");
+    round_trip(&Aes256::new(&key), "8ea2b7ca516745bfeafc49904b496089");
+
+    assert_eq!(Aes192::KEY_SIZE, 24);
+}
+
+// NIST CAVP known answer and multi-block message tests (AESAVS). Each
+// record is run block by block through `encrypt` or `decrypt`, depending
+// on the section it sits in.
+#[cfg(test)]
+fn cavp_known_answer(kind: &str) {
+    use crate::cavp;
+
+    let mut ctx = AesContext::new();
+    let tables = gen_tables();
+
+    for &nbits in &[128, 192, 256] {
+        let path = format!("tests/cavp/aes/ECB{}{}.rsp", kind, nbits);
+        let sections = cavp::load(&path);
+        assert_eq!(sections.len(), 2, "{}", path);
+
+        for section in &sections {
+            let forward = section.header == "ENCRYPT";
+
+            for record in &section.records {
+                let key = record.bytes("KEY");
+                assert_eq!(key.len() * 8, nbits, "{} COUNT = {}", path, record.get("COUNT"));
+
+                set_key(&mut ctx, &tables, &key, nbits as isize);
+
+                let (input, expected) = if forward {
+                    (record.bytes("PLAINTEXT"), record.bytes("CIPHERTEXT"))
+                } else {
+                    (record.bytes("CIPHERTEXT"), record.bytes("PLAINTEXT"))
+                };
+
+                let mut output = vec![];
+                for chunk in input.chunks(16) {
+                    let mut block = [0u8; 16];
+                    let mut out = [0u8; 16];
+                    block.copy_from_slice(chunk);
+
+                    if forward {
+                        encrypt(&ctx, &tables, block, &mut out);
+                    } else {
+                        decrypt(&ctx, &tables, block, &mut out);
+                    }
+
+                    output.extend_from_slice(&out);
+                }
+
+                assert_eq!(output, expected, "{} [{}] COUNT = {}", path, section.header, record.get("COUNT"));
+            }
+        }
+    }
+}
+
+#[test]
+fn cavp_gfsbox() {
+    cavp_known_answer("GFSbox");
+}
+
+#[test]
+fn cavp_keysbox() {
+    cavp_known_answer("KeySbox");
+}
+
+#[test]
+fn cavp_varkey() {
+    cavp_known_answer("VarKey");
+}
+
+#[test]
+fn cavp_vartxt() {
+    cavp_known_answer("VarTxt");
+}
+
+#[test]
+fn cavp_mmt() {
+    cavp_known_answer("MMT");
+}
+
+// AESAVS Monte Carlo test, section 6.4.1. Each record holds the key and
+// text going in to 1000 chained operations and the last block out; the
+// next key folds in the last one or two outputs.
+#[test]
+fn cavp_mct() {
+    use crate::cavp;
+
+    let mut ctx = AesContext::new();
+    let tables = gen_tables();
+
+    for &nbits in &[128, 192, 256] {
+        let path = format!("tests/cavp/aes/ECBMCT{}.rsp", nbits);
+        let nk = nbits / 8;
+
+        for section in &cavp::load(&path) {
+            let forward = section.header == "ENCRYPT";
+            let (from, to) = if forward { ("PLAINTEXT", "CIPHERTEXT") } else { ("CIPHERTEXT", "PLAINTEXT") };
+
+            let mut key = section.records[0].bytes("KEY");
+            let mut text = [0u8; 16];
+            text.copy_from_slice(&section.records[0].bytes(from));
+
+            for record in &section.records {
+                // the chain must agree with the file at every step
+                assert_eq!(key, record.bytes("KEY"), "{} [{}] COUNT = {}", path, section.header, record.get("COUNT"));
+                assert_eq!(text.to_vec(), record.bytes(from));
+
+                set_key(&mut ctx, &tables, &key, nbits as isize);
+
+                let mut previous = [0u8; 16];
+                for _ in 0..1000 {
+                    previous = text;
+                    if forward {
+                        encrypt(&ctx, &tables, previous, &mut text);
+                    } else {
+                        decrypt(&ctx, &tables, previous, &mut text);
+                    }
+                }
+
+                assert_eq!(text.to_vec(), record.bytes(to), "{} [{}] COUNT = {}", path, section.header, record.get("COUNT"));
+
+                // `previous` is the second to last output
+                let mut tail = previous.to_vec();
+                tail.extend_from_slice(&text);
+
+                for (k, t) in key.iter_mut().zip(&tail[32 - nk..]) {
+                    *k ^= t;
+                }
+            }
+        }
     }
 }
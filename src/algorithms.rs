@@ -24,8 +24,8 @@ pub(crate) fn put_u32(state: u32, data: &mut [u8], index: usize) {
     )
 }
 
-#[inline(always)] pub(crate) fn rotr8(x: u32) -> u32 {  ( ( x.wrapping_shl(24) ) & 0xFFFFFFFF )
-                                              | ( ( x & 0xFFFFFFFF ).wrapping_shr(8) ) }
+#[inline(always)] pub(crate) fn rotr8(x: u32) -> u32 {  ( x.wrapping_shl(24) )
+                                              | ( x.wrapping_shr(8) ) }
 
 // SHRⁿ(x) = x >> n
 #[inline(always)] fn shr(x: u32, n: u32)  -> u32 { (x).wrapping_shr(n)    }
@@ -59,3 +59,14 @@ pub(crate) fn p(a: u32, b: u32, c: u32, d: &mut u32, e: u32, f: u32, g: u32, h:
     *d = (*d).wrapping_add(temp1);
     *h = temp1.wrapping_add(temp2);
 }
+
+#[inline(always)]
+pub(crate) fn get_u64(data: &[u8], index: usize) -> u64 {
+    (get_u32(data, index) as u64) << 32 | get_u32(data, index + 4) as u64
+}
+
+#[inline(always)]
+pub(crate) fn put_u64(state: u64, data: &mut [u8], index: usize) {
+    put_u32((state >> 32) as u32, data, index);
+    put_u32(state as u32, data, index + 4);
+}
@@ -0,0 +1,300 @@
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use crate::AesHeaderInfo;
+#[cfg(test)]
+use crate::testing::{fixture, Scratch};
+use crate::error::Result;
+use crate::validate::{Confidence, Validation, ValidationIssue};
+
+const CHUNK_SIZE: usize = 64 * 1024;
+
+// What ended a carved payload. Nothing in the format records its length,
+// so the extent runs up to whatever follows and is then trimmed back to a
+// whole number of cipher blocks (and a plausible modulo octet).
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Extent {
+    NextSignature,
+    EndOfData,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Carved {
+    pub offset: u64,
+    pub length: u64,
+    pub version: u8,
+    pub extent: Extent,
+    // structural checks over the estimated extent
+    pub validation: Validation,
+}
+
+impl Carved {
+    pub fn confidence(&self) -> Confidence {
+        self.validation.confidence
+    }
+}
+
+// Finds AES Crypt payloads embedded in a disk image, memory dump or any
+// other seekable stream.
+
+pub struct Carver<R: Read + Seek> {
+    inner: R,
+}
+
+impl<R: Read + Seek> Carver<R> {
+    pub fn new(inner: R) -> Self {
+        Carver { inner }
+    }
+
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+
+    // Every 'AES' + version signature whose header holds up, in stream
+    // order. Candidates with a broken header are dropped; the rest carry
+    // their validation so callers can filter on confidence.
+    pub fn scan(&mut self) -> Result<Vec<Carved>> {
+        let size = self.inner.seek(SeekFrom::End(0))?;
+
+        let mut headers = vec![];
+        for offset in self.signatures()? {
+            let validation = Validation::from_reader(Window::new(&mut self.inner, offset, size))?;
+
+            if sound_header(&validation) { headers.push((offset, validation.version.unwrap_or(0))) }
+        }
+
+        let mut carved = vec![];
+        for (i, &(offset, version)) in headers.iter().enumerate() {
+            let (bound, extent) = match headers.get(i + 1) {
+                Some(&(next, _)) => (next, Extent::NextSignature),
+                None => (size, Extent::EndOfData),
+            };
+
+            let length = self.fit(offset, bound)?;
+            let validation = Validation::from_reader(Window::new(&mut self.inner, offset, offset + length))?;
+
+            carved.push(Carved { offset, length, version, extent, validation });
+        }
+
+        Ok(carved)
+    }
+
+    // Copies a carved payload to `output`, returning the octets written.
+    pub fn extract<W: Write>(&mut self, carved: &Carved, output: &mut W) -> Result<u64> {
+        let mut window = Window::new(&mut self.inner, carved.offset, carved.offset + carved.length);
+        Ok(io::copy(&mut window, output)?)
+    }
+
+    // Writes each payload to `dir` as `carved-<offset>.v<version>.aes`.
+    pub fn extract_to_dir<P: AsRef<Path>>(&mut self, carved: &[Carved], dir: P) -> Result<Vec<PathBuf>> {
+        let mut paths = vec![];
+
+        for c in carved {
+            let path = dir.as_ref().join(format!("carved-{:010x}.v{}.aes", c.offset, c.version));
+
+            let mut output = File::create(&path)?;
+            self.extract(c, &mut output)?;
+            output.sync_all()?;
+
+            paths.push(path);
+        }
+
+        Ok(paths)
+    }
+
+    fn signatures(&mut self) -> Result<Vec<u64>> {
+        self.inner.seek(SeekFrom::Start(0))?;
+
+        let mut found = vec![];
+        let mut buf = vec![0u8; CHUNK_SIZE + 3];
+        // stream offset of buf[0], and octets carried over from the last chunk
+        let mut base = 0u64;
+        let mut carried = 0;
+
+        loop {
+            let n = match self.inner.read(&mut buf[carried..]) {
+                Ok(0) => break,
+                Ok(n) => n,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e.into()),
+            };
+
+            let filled = carried + n;
+
+            for p in 0..filled.saturating_sub(3) {
+                if &buf[p..p + 3] == b"AES" && buf[p + 3] <= 3 { found.push(base + p as u64) }
+            }
+
+            // a signature may straddle the chunk boundary
+            let keep = filled.min(3);
+            buf.copy_within(filled - keep..filled, 0);
+            base += (filled - keep) as u64;
+            carried = keep;
+        }
+
+        Ok(found)
+    }
+
+    // Longest plausible payload starting at `offset` and ending by `bound`.
+    fn fit(&mut self, offset: u64, bound: u64) -> Result<u64> {
+        let info = match AesHeaderInfo::from_reader(Window::new(&mut self.inner, offset, bound)) {
+            Ok(info) => info,
+            // too short to hold even the header; keep all of it
+            Err(_) => return Ok(bound - offset),
+        };
+
+        let trailer = info.file_size - info.ciphertext_end;
+        let mut ciphertext = (info.ciphertext_end - info.ciphertext_start) / 16 * 16;
+
+        loop {
+            let length = info.ciphertext_start + ciphertext + trailer;
+
+            if (info.version != 1 && info.version != 2) || ciphertext == 0 { return Ok(length) }
+
+            let mut modulo = [0u8; 1];
+            self.inner.seek(SeekFrom::Start(offset + length - trailer))?;
+            self.inner.read_exact(&mut modulo)?;
+
+            if modulo[0] < 16 { return Ok(length) }
+
+            ciphertext -= 16;
+        }
+    }
+}
+
+// Issues that depend on where the payload ends are expected before its
+// extent is known; anything else means the signature is a coincidence.
+fn sound_header(validation: &Validation) -> bool {
+    if validation.confidence == Confidence::Rejected { return false }
+
+    validation.issues.iter().all(|issue| match *issue {
+        ValidationIssue::TooSmall { .. } | ValidationIssue::UnalignedCiphertext(_) => true,
+        ValidationIssue::ModuloOutOfRange(_) => validation.version != Some(0),
+        _ => false,
+    })
+}
+
+// A seekable view of `start..end` of the underlying stream.
+struct Window<'a, R: Read + Seek> {
+    inner: &'a mut R,
+    start: u64,
+    end: u64,
+    position: u64,
+}
+
+impl<'a, R: Read + Seek> Window<'a, R> {
+    fn new(inner: &'a mut R, start: u64, end: u64) -> Self {
+        Window { inner, start, end, position: 0 }
+    }
+}
+
+impl<'a, R: Read + Seek> Read for Window<'a, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let left = (self.end - self.start).saturating_sub(self.position);
+        if left == 0 { return Ok(0) }
+
+        let want = buf.len().min(left as usize);
+
+        self.inner.seek(SeekFrom::Start(self.start + self.position))?;
+        let n = self.inner.read(&mut buf[..want])?;
+        self.position += n as u64;
+
+        Ok(n)
+    }
+}
+
+impl<'a, R: Read + Seek> Seek for Window<'a, R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let position = match pos {
+            SeekFrom::Start(n) => n as i64,
+            SeekFrom::End(n) => (self.end - self.start) as i64 + n,
+            SeekFrom::Current(n) => self.position as i64 + n,
+        };
+
+        if position < 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "seek before the start of the window"))
+        }
+
+        self.position = position as u64;
+        Ok(self.position)
+    }
+}
+
+#[cfg(test)]
+fn junk(len: usize, seed: u32) -> Vec<u8> {
+    // small LCG, kept clear of the 'A' that starts a signature
+    let mut state = seed;
+    (0..len).map(|_| {
+        state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+        match (state >> 16) as u8 { b'A' => b'a', b => b }
+    }).collect()
+}
+
+#[test]
+fn carve_back_to_back() {
+    let v2 = fixture("hello_v2.txt.aes");
+    let v0 = fixture("hello_v0.txt.aes");
+    let v3 = fixture("aligned_v3.txt.aes");
+
+    let mut image = junk(1000, 7);
+    image.extend_from_slice(&v2);
+    image.extend_from_slice(&v0);
+    // a coincidental signature in plain text
+    image.extend_from_slice(b"AES\x01 no header");
+    image.extend_from_slice(&v3);
+
+    let mut carver = Carver::new(io::Cursor::new(image));
+    let carved = carver.scan().unwrap();
+
+    let found: Vec<_> = carved.iter().map(|c| (c.offset, c.version, c.confidence())).collect();
+    assert_eq!(found, vec![
+        (1000, 2, Confidence::High),
+        (1324, 0, Confidence::Medium),
+        (1409 + 14, 3, Confidence::High),
+    ]);
+
+    assert_eq!(carved[0].length, v2.len() as u64);
+    assert_eq!(carved[0].extent, Extent::NextSignature);
+    // the plain text after v0 is trimmed away with the block alignment
+    assert_eq!(carved[1].length, v0.len() as u64);
+    assert_eq!(carved[2].length, v3.len() as u64);
+    assert_eq!(carved[2].extent, Extent::EndOfData);
+
+    let mut extracted = vec![];
+    carver.extract(&carved[0], &mut extracted).unwrap();
+    assert_eq!(extracted, v2);
+    assert_eq!(crate::decrypt::decrypt(&extracted, "password").unwrap(), b"Hello, AES Crypt!\n");
+}
+
+#[test]
+fn carve_trailing_junk() {
+    let v1 = fixture("hello_v1.txt.aes");
+
+    let mut image = v1.clone();
+    image.extend_from_slice(&junk(100, 3));
+
+    let carved = Carver::new(io::Cursor::new(image)).scan().unwrap();
+
+    // the estimate can only overshoot, by whole blocks
+    assert_eq!(carved.len(), 1);
+    assert!(carved[0].length >= v1.len() as u64);
+    assert_eq!((carved[0].length - v1.len() as u64) % 16, 0);
+    assert!(carved[0].validation.is_valid());
+}
+
+#[test]
+fn carve_to_dir() {
+    let dir = Scratch::new("carve-to-dir");
+
+    let mut image = junk(70_000, 11);
+    image.extend_from_slice(&fixture("hello_v3.txt.aes"));
+
+    let mut carver = Carver::new(io::Cursor::new(image));
+    let carved = carver.scan().unwrap();
+    let paths = carver.extract_to_dir(&carved, &dir).unwrap();
+
+    assert_eq!(paths, vec![dir.join("carved-0000011170.v3.aes")]);
+    assert_eq!(std::fs::read(&paths[0]).unwrap(), fixture("hello_v3.txt.aes"));
+}
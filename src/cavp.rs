@@ -0,0 +1,120 @@
+// Reader for the NIST CAVP response (.rsp) files under tests/cavp.
+//
+// A file is a run of `[...]` section headers, each followed by records of
+// `NAME = value` lines. Records are separated by blank lines; `#` starts a
+// comment.
+
+use std::fs;
+
+pub(crate) struct Record {
+    fields: Vec<(String, String)>,
+}
+
+impl Record {
+    pub(crate) fn get(&self, name: &str) -> &str {
+        self.fields.iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v.as_str())
+            .unwrap_or_else(|| panic!("record has no {}", name))
+    }
+
+    pub(crate) fn bytes(&self, name: &str) -> Vec<u8> {
+        unhex(self.get(name))
+    }
+
+    pub(crate) fn number(&self, name: &str) -> usize {
+        self.get(name).parse().unwrap()
+    }
+}
+
+pub(crate) struct Section {
+    // text between the brackets, e.g. "ENCRYPT" or "L = 32"
+    pub(crate) header: String,
+    pub(crate) records: Vec<Record>,
+}
+
+pub(crate) fn load(path: &str) -> Vec<Section> {
+    let text = fs::read_to_string(path).unwrap_or_else(|e| panic!("{}: {}", path, e));
+    parse(&text)
+}
+
+pub(crate) fn parse(text: &str) -> Vec<Section> {
+    let mut sections = vec![Section { header: String::new(), records: vec![] }];
+    let mut fields: Vec<(String, String)> = vec![];
+
+    for line in text.lines() {
+        let line = line.trim();
+
+        if line.is_empty() || line.starts_with('#') || line.starts_with('[') {
+            if !fields.is_empty() {
+                sections.last_mut().unwrap().records.push(Record { fields: fields.split_off(0) });
+            }
+
+            if line.starts_with('[') {
+                let header = line.trim_start_matches('[').trim_end_matches(']').trim();
+                sections.push(Section { header: header.to_string(), records: vec![] });
+            }
+
+            continue
+        }
+
+        let mut parts = line.splitn(2, '=');
+        let name = parts.next().unwrap().trim().to_string();
+        let value = parts.next().unwrap_or("").trim().to_string();
+
+        // a repeated name starts the next record even without a blank line
+        if fields.iter().any(|(n, _)| *n == name) {
+            sections.last_mut().unwrap().records.push(Record { fields: fields.split_off(0) });
+        }
+
+        fields.push((name, value));
+    }
+
+    if !fields.is_empty() {
+        sections.last_mut().unwrap().records.push(Record { fields });
+    }
+
+    sections.retain(|s| !s.header.is_empty() || !s.records.is_empty());
+    sections
+}
+
+pub(crate) fn unhex(text: &str) -> Vec<u8> {
+    assert!(text.len().is_multiple_of(2), "odd length hex {:?}", text);
+
+    (0..text.len()).step_by(2)
+        .map(|i| u8::from_str_radix(&text[i..i + 2], 16).unwrap())
+        .collect()
+}
+
+#[test]
+fn parse_rsp() {
+    let sections = parse("\
+# CAVS 11.1
+# comment
+
+[ENCRYPT]
+
+COUNT = 0
+KEY = 00ff
+PLAINTEXT = 0a
+
+COUNT = 1
+KEY = 01
+PLAINTEXT =
+[DECRYPT]
+COUNT = 0
+KEY = 02
+COUNT = 1
+KEY = 03
+");
+
+    assert_eq!(sections.len(), 2);
+    assert_eq!(sections[0].header, "ENCRYPT");
+    assert_eq!(sections[0].records.len(), 2);
+    assert_eq!(sections[0].records[0].bytes("KEY"), vec![0x00, 0xff]);
+    assert_eq!(sections[0].records[1].get("PLAINTEXT"), "");
+
+    assert_eq!(sections[1].header, "DECRYPT");
+    let counts: Vec<_> = sections[1].records.iter().map(|r| r.number("COUNT")).collect();
+    assert_eq!(counts, vec![0, 1]);
+}
@@ -0,0 +1,159 @@
+use crate::aes::{Aes256, BlockCipher};
+use crate::sha256::{self, starts, update, finish};
+use crate::sha512;
+
+// AES Crypt password stretching: the IV seeds a 32 octet digest which is
+// then hashed together with the UTF-16LE password 8192 times.
+
+pub(crate) fn derive_key(password: &str, iv: &[u8; 16]) -> [u8; 32] {
+    let passwd: Vec<u8> = password.encode_utf16()
+        .flat_map(|c| vec![c as u8, (c >> 8) as u8])
+        .collect();
+
+    let mut digest = [0u8; 32];
+    digest[..16].copy_from_slice(iv);
+
+    for _ in 0..8192 {
+        let mut ctx = starts(None);
+
+        update(&mut ctx, &digest, &mut 32);
+        update(&mut ctx, &passwd, &mut (passwd.len() as u32));
+
+        finish(&mut ctx, &mut digest);
+    }
+
+    digest
+}
+
+// PBKDF2-HMAC-SHA512 as used by version 3 files. AES Crypt only needs the
+// first 32 octets, which all come from the first output block.
+
+pub(crate) fn pbkdf2_sha512(password: &str, salt: &[u8], iterations: u32) -> [u8; 32] {
+    let base = sha512::hmac_starts(password.as_bytes());
+
+    let mut u = [0u8; 64];
+    let mut ctx = base.clone();
+
+    sha512::hmac_update(&mut ctx, salt);
+    sha512::hmac_update(&mut ctx, &[0x00, 0x00, 0x00, 0x01]);
+    sha512::hmac_finish(&mut ctx, &mut u);
+
+    let mut t = u;
+
+    for _ in 1..iterations {
+        let mut ctx = base.clone();
+
+        sha512::hmac_update(&mut ctx, &u);
+        sha512::hmac_finish(&mut ctx, &mut u);
+
+        for i in 0..64 { t[i] ^= u[i]; }
+    }
+
+    let mut key = [0u8; 32];
+    key.copy_from_slice(&t[..32]);
+    key
+}
+
+// AES-256 in CBC mode, carrying the chaining block between calls so that
+// a message can be processed in pieces.
+
+pub(crate) struct Cbc {
+    cipher: Aes256,
+    iv: [u8; 16],
+}
+
+impl Cbc {
+    pub(crate) fn new(key: &[u8; 32], iv: &[u8; 16]) -> Self {
+        Cbc { cipher: Aes256::new(key), iv: *iv }
+    }
+
+    // `data` must be a whole number of 16 octet blocks
+    pub(crate) fn encrypt(&mut self, data: &mut [u8]) {
+        for chunk in data.chunks_mut(16) {
+            for i in 0..16 { self.iv[i] ^= chunk[i]; }
+
+            self.cipher.encrypt_block(&mut self.iv);
+
+            chunk.copy_from_slice(&self.iv);
+        }
+    }
+
+    pub(crate) fn decrypt(&mut self, data: &mut [u8]) {
+        for chunk in data.chunks_mut(16) {
+            let mut block = [0u8; 16];
+            block.copy_from_slice(chunk);
+
+            let mut plain = block;
+            self.cipher.decrypt_block(&mut plain);
+
+            for i in 0..16 { chunk[i] = plain[i] ^ self.iv[i]; }
+
+            self.iv = block;
+        }
+    }
+}
+
+pub(crate) fn cbc_encrypt(key: &[u8; 32], iv: &[u8; 16], input: &[u8]) -> Vec<u8> {
+    let mut output = input.to_vec();
+    Cbc::new(key, iv).encrypt(&mut output);
+    output
+}
+
+pub(crate) fn cbc_decrypt(key: &[u8; 32], iv: &[u8; 16], input: &[u8]) -> Vec<u8> {
+    let mut output = input.to_vec();
+    Cbc::new(key, iv).decrypt(&mut output);
+    output
+}
+
+// compare two MACs without short-circuiting on the first mismatch
+
+pub(crate) fn hmac_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() { return false }
+
+    a.iter().zip(b.iter()).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[test]
+fn cbc_round_trip() {
+    use crate::util::SliceToHex;
+
+    let key = [0x2Bu8; 32];
+    let iv = [0x07u8; 16];
+    let plain: Vec<u8> = (0..64).collect();
+
+    let cipher = cbc_encrypt(&key, &iv, &plain);
+
+    assert_eq!(
+        <[u8]>::slice_to_hex(&cipher),
+        "9e8a4a2664bdacec251f322ea7933abe431c5c1e5a51ec92e9dbe039d9871d25\
+         cd7974581387811aace5f906bf5be99fc0d5158b94031b15f7686fa2819d789b"
+    );
+    assert_eq!(cbc_decrypt(&key, &iv, &cipher), plain);
+}
+
+#[test]
+fn password_stretch() {
+    use crate::util::SliceToHex;
+
+    let mut iv = [0u8; 16];
+    for i in 0..16 { iv[i] = i as u8; }
+
+    assert_eq!(
+        <[u8]>::slice_to_hex(&derive_key("password", &iv)),
+        "a3c1eba8f6b8f7172f5e562ff8e3c9ece64463289c4e7d0cb74ff61b07069382"
+    );
+}
+
+#[test]
+fn pbkdf2_sha512_vectors() {
+    use crate::util::SliceToHex;
+
+    assert_eq!(
+        <[u8]>::slice_to_hex(&pbkdf2_sha512("password", b"salt", 1)),
+        "867f70cf1ade02cff3752599a3a53dc4af34c7a669815ae5d513554e1c8cf252"
+    );
+    assert_eq!(
+        <[u8]>::slice_to_hex(&pbkdf2_sha512("password", b"salt", 4096)),
+        "d197b1b33db0143e018b12f3d1d1479e6cdebdcc97c5c0f87f6902e072f457b5"
+    );
+}
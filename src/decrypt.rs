@@ -0,0 +1,642 @@
+use std::io::{self, Read};
+
+use byteorder::{BigEndian, ByteOrder};
+
+use crate::error::{Error, Result};
+use crate::header::trailer_size;
+use crate::util::Counted;
+#[cfg(test)]
+use crate::testing::fixture;
+use crate::crypt::{cbc_decrypt, derive_key, hmac_eq, pbkdf2_sha512, Cbc};
+use crate::sha256::{hmac_starts, hmac_update, hmac_finish, SHA256Context};
+
+// upper bound on the version 3 KDF iteration count accepted from a header
+pub(crate) const MAX_KDF_ITERATIONS: u32 = 5_000_000;
+
+// `Read::read_exact` that reports how much of `buf` could be filled
+pub(crate) fn read_exact<R: Read>(input: &mut R, buf: &mut [u8]) -> Result<()> {
+    let mut read = 0;
+
+    while read < buf.len() {
+        match input.read(&mut buf[read..]) {
+            Ok(0) => return Err(Error::Truncated { expected: buf.len() as u64, actual: read as u64 }),
+            Ok(n) => read += n,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => (),
+            Err(e) => return Err(e.into()),
+        }
+    }
+
+    Ok(())
+}
+
+fn read_array16<R: Read>(input: &mut R) -> Result<[u8; 16]> {
+    let mut buf = [0u8; 16];
+    read_exact(input, &mut buf)?;
+    Ok(buf)
+}
+
+fn read_array32<R: Read>(input: &mut R) -> Result<[u8; 32]> {
+    let mut buf = [0u8; 32];
+    read_exact(input, &mut buf)?;
+    Ok(buf)
+}
+
+fn skip_extensions<R: Read>(input: &mut R) -> Result<()> {
+    loop {
+        let mut ext_len = [0u8; 2];
+        read_exact(input, &mut ext_len)?;
+
+        let length = BigEndian::read_u16(&ext_len) as u64;
+        if length == 0 { return Ok(()) }
+
+        let skipped = io::copy(&mut input.take(length), &mut io::sink())?;
+        if skipped != length {
+            return Err(Error::Truncated { expected: length, actual: skipped })
+        }
+    }
+}
+
+pub(crate) fn read_iterations<R: Read>(input: &mut R) -> Result<u32> {
+    let mut count = [0u8; 4];
+    read_exact(input, &mut count)?;
+
+    match BigEndian::read_u32(&count) {
+        n if n == 0 || n > MAX_KDF_ITERATIONS => Err(Error::InvalidKdfIterations(n)),
+        n => Ok(n),
+    }
+}
+
+// Reads the version 3 header up to and including the KDF iteration count.
+
+pub(crate) fn v3_iterations<R: Read>(input: &mut R) -> Result<u32> {
+    let mut header = [0u8; 5];
+    read_exact(input, &mut header)?;
+
+    skip_extensions(input)?;
+
+    read_iterations(input)
+}
+
+pub(crate) struct SessionBlock {
+    pub(crate) iv: [u8; 16],
+    block: [u8; 48],
+    mac: [u8; 32],
+}
+
+pub(crate) fn read_session<R: Read>(input: &mut R) -> Result<SessionBlock> {
+    let iv = read_array16(input)?;
+
+    let mut block = [0u8; 48];
+    read_exact(input, &mut block)?;
+
+    let mac = read_array32(input)?;
+
+    Ok(SessionBlock { iv, block, mac })
+}
+
+// Checks the HMAC of the 48 octet IV + key block (followed by `suffix`,
+// which version 3 uses for its version octet) and decrypts the pair.
+
+pub(crate) fn open_session(session: &SessionBlock, key: &[u8; 32], suffix: &[u8]) -> Result<([u8; 16], [u8; 32])> {
+    let mut digest = [0u8; 32];
+    let mut ctx = hmac_starts(key);
+
+    hmac_update(&mut ctx, &session.block);
+    hmac_update(&mut ctx, suffix);
+    hmac_finish(&mut ctx, &mut digest);
+
+    if !hmac_eq(&digest, &session.mac) { return Err(Error::HmacMismatch) }
+
+    let plain = cbc_decrypt(key, &session.iv, &session.block);
+
+    let mut iv2 = [0u8; 16];
+    let mut key2 = [0u8; 32];
+    iv2.copy_from_slice(&plain[..16]);
+    key2.copy_from_slice(&plain[16..]);
+
+    Ok((iv2, key2))
+}
+
+// size of the reads made against the underlying stream
+const CHUNK_SIZE: usize = 64 * 1024;
+
+// Decrypts an AES Crypt stream of any version as it is read. The message
+// HMAC can only be checked once the end of the stream is reached, so the
+// final `read` returns an error if the data turns out to be altered.
+
+pub struct AesCryptReader<R: Read> {
+    inner: R,
+    version: u8,
+    cbc: Cbc,
+    mac: SHA256Context,
+    // modulo octet from a version 0 header
+    modulo: u8,
+    // octets that follow the ciphertext: modulo octet and/or HMAC
+    trailer: usize,
+    // ciphertext (and possibly trailer) not yet processed
+    pending: Vec<u8>,
+    plain: Vec<u8>,
+    position: usize,
+    done: bool,
+}
+
+impl<R: Read> AesCryptReader<R> {
+    pub fn new(mut inner: R, password: &str) -> Result<Self> {
+        let mut header = [0u8; 5];
+        read_exact(&mut inner, &mut header)?;
+
+        if &header[..3] != b"AES" { return Err(Error::NotAesCrypt) }
+
+        let version = header[3];
+
+        let (key, iv) = match version {
+            // Version 0 encrypts the message directly with the password
+            // derived key
+            0 => {
+                let iv = read_array16(&mut inner)?;
+                (derive_key(password, &iv), iv)
+            },
+            1 | 2 => {
+                if version == 2 { skip_extensions(&mut inner)?; }
+
+                let session = read_session(&mut inner)?;
+                let (iv, key) = open_session(&session, &derive_key(password, &session.iv), &[])?;
+                (key, iv)
+            },
+            3 => {
+                skip_extensions(&mut inner)?;
+
+                let iterations = read_iterations(&mut inner)?;
+                let session = read_session(&mut inner)?;
+                let key = pbkdf2_sha512(password, &session.iv, iterations);
+                let (iv, key) = open_session(&session, &key, &[0x03])?;
+                (key, iv)
+            },
+            v => return Err(Error::UnsupportedVersion(v)),
+        };
+
+        Ok(AesCryptReader {
+            inner,
+            version,
+            cbc: Cbc::new(&key, &iv),
+            mac: hmac_starts(&key),
+            modulo: header[4],
+            trailer: trailer_size(version) as usize,
+            pending: Vec::with_capacity(CHUNK_SIZE + 64),
+            plain: vec![],
+            position: 0,
+            done: false,
+        })
+    }
+
+    pub fn version(&self) -> u8 {
+        self.version
+    }
+
+    // Deciphers every whole block that cannot be part of the trailer or the
+    // final (possibly partial) block.
+    fn process(&mut self) {
+        let keep = self.trailer + 16;
+        if self.pending.len() <= keep { return }
+
+        let n = (self.pending.len() - keep) / 16 * 16;
+        let mut blocks: Vec<u8> = self.pending.drain(..n).collect();
+
+        hmac_update(&mut self.mac, &blocks);
+        self.cbc.decrypt(&mut blocks);
+
+        self.plain = blocks;
+        self.position = 0;
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        let (mut blocks, modulo) = self.check_mac()?;
+
+        self.cbc.decrypt(&mut blocks);
+
+        if self.version == 3 {
+            trim_padding(&mut blocks)?;
+        } else {
+            trim_modulo(&mut blocks, modulo)?;
+        }
+
+        self.plain = blocks;
+        self.position = 0;
+
+        Ok(())
+    }
+
+    // Splits the trailer off what is left of the stream and checks the
+    // message HMAC; returns the remaining ciphertext and the modulo octet.
+    fn check_mac(&mut self) -> Result<(Vec<u8>, u8)> {
+        self.done = true;
+
+        if self.pending.len() < self.trailer {
+            return Err(Error::Truncated {
+                expected: self.trailer as u64,
+                actual: self.pending.len() as u64,
+            })
+        }
+
+        let split = self.pending.len() - self.trailer;
+        let blocks: Vec<u8> = self.pending.drain(..split).collect();
+        let trailer = std::mem::take(&mut self.pending);
+
+        // a partial final block means octets went missing
+        if !blocks.len().is_multiple_of(16) {
+            return Err(Error::Truncated {
+                expected: ((blocks.len() / 16 + 1) * 16 + self.trailer) as u64,
+                actual: (blocks.len() + self.trailer) as u64,
+            })
+        }
+
+        let (modulo, expected) = match self.trailer {
+            33 => (trailer[0], &trailer[1..]),
+            _ => (self.modulo, &trailer[..]),
+        };
+
+        hmac_update(&mut self.mac, &blocks);
+
+        let mut digest = [0u8; 32];
+        hmac_finish(&mut self.mac, &mut digest);
+
+        if !hmac_eq(&digest, expected) { return Err(Error::HmacMismatch) }
+
+        Ok((blocks, modulo))
+    }
+
+    // Runs the rest of the stream through the message HMAC without
+    // deciphering any of it.
+    fn authenticate(&mut self) -> Result<()> {
+        let mut chunk = vec![0u8; CHUNK_SIZE];
+
+        loop {
+            let read = match self.inner.read(&mut chunk) {
+                Ok(0) => break,
+                Ok(read) => read,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e.into()),
+            };
+
+            self.pending.extend_from_slice(&chunk[..read]);
+
+            // the trailer and final block are left for `check_mac`
+            let keep = self.trailer + 16;
+            if self.pending.len() > keep {
+                let n = (self.pending.len() - keep) / 16 * 16;
+                hmac_update(&mut self.mac, &self.pending[..n]);
+                self.pending.drain(..n);
+            }
+        }
+
+        self.check_mac().map(|_| ())
+    }
+}
+
+// Whether `password` opens the stream. Versions 1 to 3 are settled by the
+// HMAC over the encrypted session key alone; version 0 has no session key,
+// so its message HMAC is taken over the ciphertext, which is not deciphered.
+
+pub(crate) fn check_password<R: Read>(input: R, password: &str) -> Result<bool> {
+    let mut reader = match AesCryptReader::new(input, password) {
+        Ok(reader) => reader,
+        Err(Error::HmacMismatch) => return Ok(false),
+        Err(e) => return Err(e),
+    };
+
+    if reader.version != 0 { return Ok(true) }
+
+    match reader.authenticate() {
+        Ok(()) => Ok(true),
+        Err(Error::HmacMismatch) => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+impl<R: Read> Read for AesCryptReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            if self.position < self.plain.len() {
+                let n = buf.len().min(self.plain.len() - self.position);
+                buf[..n].copy_from_slice(&self.plain[self.position..self.position + n]);
+                self.position += n;
+                return Ok(n)
+            }
+
+            if self.done { return Ok(0) }
+
+            let start = self.pending.len();
+            self.pending.resize(start + CHUNK_SIZE, 0);
+
+            let read = match self.inner.read(&mut self.pending[start..]) {
+                Ok(read) => read,
+                Err(e) => {
+                    self.pending.truncate(start);
+                    if e.kind() == io::ErrorKind::Interrupted { continue }
+                    return Err(e)
+                },
+            };
+
+            self.pending.truncate(start + read);
+
+            if read == 0 {
+                self.finish()?;
+            } else {
+                self.process();
+            }
+        }
+    }
+}
+
+// keeps only `modulo` octets of the final block when that is non-zero
+fn trim_modulo(plain: &mut Vec<u8>, modulo: u8) -> Result<()> {
+    let modulo = modulo as usize & 0x0F;
+    if modulo == 0 { return Ok(()) }
+
+    if plain.is_empty() { return Err(Error::InvalidPadding) }
+
+    let len = plain.len() - 16 + modulo;
+    plain.truncate(len);
+
+    Ok(())
+}
+
+// removes PKCS#7 padding
+fn trim_padding(plain: &mut Vec<u8>) -> Result<()> {
+    let pad = *plain.last().ok_or(Error::InvalidPadding)? as usize;
+
+    if pad == 0 || pad > 16 || plain[plain.len() - pad..].iter().any(|b| *b as usize != pad) {
+        return Err(Error::InvalidPadding)
+    }
+
+    let len = plain.len() - pad;
+    plain.truncate(len);
+
+    Ok(())
+}
+
+// Outcome of `verify`. Truncation is only noticed when the stream stops
+// short of a whole block or of its trailer; `offset` is where it ended.
+// Version 0 has no separate password check, so there a wrong password
+// shows up as `Altered`.
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Verification {
+    Intact,
+    WrongPassword,
+    Altered,
+    Truncated { offset: u64 },
+}
+
+impl Verification {
+    pub fn is_intact(&self) -> bool {
+        *self == Verification::Intact
+    }
+}
+
+// Checks a whole stream against its HMACs. Only the session key is
+// deciphered; the message is authenticated and then discarded.
+pub fn verify<R: Read>(input: R, password: &str) -> Result<Verification> {
+    let mut read = 0;
+
+    let outcome = match AesCryptReader::new(Counted { inner: input, count: &mut read }, password) {
+        Err(Error::HmacMismatch) => Ok(Verification::WrongPassword),
+        Err(e) => Err(e),
+        Ok(mut reader) => match reader.authenticate() {
+            Ok(()) => Ok(Verification::Intact),
+            Err(Error::HmacMismatch) => Ok(Verification::Altered),
+            Err(e) => Err(e),
+        },
+    };
+
+    match outcome {
+        Err(Error::Truncated { .. }) => Ok(Verification::Truncated { offset: read }),
+        outcome => outcome,
+    }
+}
+
+pub(crate) fn decrypt(bytes: &[u8], password: &str) -> Result<Vec<u8>> {
+    let mut plain = vec![];
+
+    AesCryptReader::new(bytes, password)?.read_to_end(&mut plain)?;
+
+    Ok(plain)
+}
+
+#[test]
+fn decrypt_v0_fixture() {
+    let bytes = fixture("hello_v0.txt.aes");
+
+    assert_eq!(decrypt(&bytes, "password").unwrap(), b"Hello, AES Crypt!\n");
+}
+
+#[test]
+fn decrypt_v0_block_aligned_fixture() {
+    let bytes = fixture("aligned_v0.txt.aes");
+
+    assert_eq!(decrypt(&bytes, "pässwörd").unwrap(), "0123456789abcdef".repeat(4).as_bytes());
+}
+
+#[test]
+fn decrypt_v0_wrong_password() {
+    let bytes = fixture("hello_v0.txt.aes");
+
+    match decrypt(&bytes, "hunter2") {
+        Err(Error::HmacMismatch) => (),
+        other => panic!("unexpected {:?}", other.map(|_| ())),
+    }
+}
+
+#[test]
+fn decrypt_v1_fixture() {
+    let bytes = fixture("hello_v1.txt.aes");
+
+    assert_eq!(decrypt(&bytes, "password").unwrap(), b"Hello, AES Crypt!\n");
+}
+
+#[test]
+fn decrypt_v1_block_aligned_fixture() {
+    let bytes = fixture("aligned_v1.txt.aes");
+
+    assert_eq!(decrypt(&bytes, "pässwörd").unwrap(), "0123456789abcdef".repeat(4).as_bytes());
+}
+
+#[test]
+fn decrypt_v1_wrong_password() {
+    let bytes = fixture("hello_v1.txt.aes");
+
+    match decrypt(&bytes, "hunter2") {
+        Err(Error::HmacMismatch) => (),
+        other => panic!("unexpected {:?}", other.map(|_| ())),
+    }
+}
+
+#[test]
+fn decrypt_v2_fixture() {
+    let bytes = fixture("hello_v2.txt.aes");
+
+    assert_eq!(decrypt(&bytes, "password").unwrap(), b"Hello, AES Crypt!\n");
+}
+
+#[test]
+fn decrypt_v2_block_aligned_fixture() {
+    let bytes = fixture("aligned_v2.txt.aes");
+
+    assert_eq!(decrypt(&bytes, "pässwörd").unwrap(), "0123456789abcdef".repeat(4).as_bytes());
+}
+
+#[test]
+fn decrypt_v2_wrong_password() {
+    let bytes = fixture("hello_v2.txt.aes");
+
+    match decrypt(&bytes, "hunter2") {
+        Err(Error::HmacMismatch) => (),
+        other => panic!("unexpected {:?}", other.map(|_| ())),
+    }
+}
+
+#[test]
+fn decrypt_v2_tampered_body() {
+    let mut bytes = fixture("hello_v2.txt.aes");
+    let last = bytes.len() - 40;
+    bytes[last] ^= 0x01;
+
+    match decrypt(&bytes, "password") {
+        Err(Error::HmacMismatch) => (),
+        other => panic!("unexpected {:?}", other.map(|_| ())),
+    }
+}
+
+#[test]
+fn decrypt_v3_fixture() {
+    let bytes = fixture("hello_v3.txt.aes");
+
+    assert_eq!(v3_iterations(&mut &bytes[..]).unwrap(), 1000);
+    assert_eq!(decrypt(&bytes, "password").unwrap(), b"Hello, AES Crypt!\n");
+}
+
+#[test]
+fn decrypt_v3_block_aligned_fixture() {
+    let bytes = fixture("aligned_v3.txt.aes");
+
+    assert_eq!(decrypt(&bytes, "pässwörd").unwrap(), "0123456789abcdef".repeat(4).as_bytes());
+}
+
+#[test]
+fn decrypt_v3_wrong_password() {
+    let bytes = fixture("hello_v3.txt.aes");
+
+    match decrypt(&bytes, "hunter2") {
+        Err(Error::HmacMismatch) => (),
+        other => panic!("unexpected {:?}", other.map(|_| ())),
+    }
+}
+
+#[test]
+fn decrypt_through_detected_file() {
+    for path in &[
+        "tests/fixtures/hello_v0.txt.aes",
+        "tests/fixtures/hello_v1.txt.aes",
+        "tests/fixtures/hello_v2.txt.aes",
+        "tests/fixtures/hello_v3.txt.aes",
+    ] {
+        let file = crate::detect::get_file(path).unwrap();
+        let data = file.decrypt("password").unwrap();
+
+        assert_eq!(data.data, b"Hello, AES Crypt!\n");
+        assert_eq!(data.extensions, file.extensions());
+    }
+}
+
+#[test]
+fn reader_small_reads() {
+    let bytes = fixture("aligned_v2.txt.aes");
+    let mut reader = AesCryptReader::new(&bytes[..], "pässwörd").unwrap();
+    let mut plain = vec![];
+    let mut buf = [0u8; 5];
+
+    loop {
+        match reader.read(&mut buf).unwrap() {
+            0 => break,
+            n => plain.extend_from_slice(&buf[..n]),
+        }
+    }
+
+    assert_eq!(reader.version(), 2);
+    assert_eq!(plain, "0123456789abcdef".repeat(4).as_bytes());
+}
+
+#[test]
+fn reader_truncated() {
+    let bytes = fixture("hello_v2.txt.aes");
+    let mut plain = vec![];
+
+    let mut reader = AesCryptReader::new(&bytes[..bytes.len() - 1], "password").unwrap();
+    let err = reader.read_to_end(&mut plain).unwrap_err();
+
+    match Error::from(err) {
+        Error::Truncated { expected: 65, actual: 64 } => (),
+        other => panic!("unexpected {:?}", other),
+    }
+}
+
+#[test]
+fn check_password_all_versions() {
+    for version in 0..4 {
+        let path = format!("tests/fixtures/hello_v{}.txt.aes", version);
+        let bytes = std::fs::read(&path).unwrap();
+
+        assert!(check_password(&bytes[..], "password").unwrap());
+        assert!(!check_password(&bytes[..], "wrong").unwrap());
+
+        let file = crate::detect::get_file(&path).unwrap();
+        assert!(file.verify_password("password").unwrap());
+        assert!(!file.verify_password("Password").unwrap());
+    }
+}
+
+#[test]
+fn check_password_ignores_body() {
+    // for v1 the body is never read, so damage there goes unnoticed
+    let mut bytes = fixture("hello_v1.txt.aes");
+    let len = bytes.len();
+    bytes[len - 40] ^= 0x01;
+
+    assert!(check_password(&bytes[..], "password").unwrap());
+
+    // v0 has nothing else to go by
+    let mut bytes = fixture("hello_v0.txt.aes");
+    bytes[30] ^= 0x01;
+
+    assert!(!check_password(&bytes[..], "password").unwrap());
+}
+
+#[test]
+fn verify_fixtures() {
+    for version in 0..4 {
+        let path = format!("tests/fixtures/aligned_v{}.txt.aes", version);
+        let bytes = std::fs::read(&path).unwrap();
+
+        assert_eq!(verify(&bytes[..], "pässwörd").unwrap(), Verification::Intact);
+
+        let expected = if version == 0 { Verification::Altered } else { Verification::WrongPassword };
+        assert_eq!(verify(&bytes[..], "password").unwrap(), expected);
+
+        let file = crate::detect::get_file(&path).unwrap();
+        assert!(file.verify("pässwörd").unwrap().is_intact());
+    }
+}
+
+#[test]
+fn verify_damage() {
+    let bytes = fixture("hello_v2.txt.aes");
+    let len = bytes.len();
+
+    let mut altered = bytes.clone();
+    altered[len - 40] ^= 0x80;
+    assert_eq!(verify(&altered[..], "password").unwrap(), Verification::Altered);
+
+    for cut in &[len - 1, len - 20, 200, 100] {
+        assert_eq!(verify(&bytes[..*cut], "password").unwrap(), Verification::Truncated { offset: *cut as u64 });
+    }
+}
@@ -0,0 +1,319 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, prelude::*, SeekFrom};
+use std::path::{Path, PathBuf};
+
+use crate::{AesHeaderInfo, Extension};
+use crate::crypt::{derive_key, pbkdf2_sha512};
+use crate::decrypt::{open_session, read_exact, read_session};
+use crate::encrypt::{push_wrapped_key, random_iv};
+use crate::extension::{encode, CONTAINER_SIZE};
+use crate::detect;
+use crate::error::{Error, Result};
+#[cfg(test)]
+use crate::testing::Scratch;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EditOutcome {
+    // the extension block was patched inside its existing footprint
+    InPlace,
+    // the extensions outgrew the container and the file was rewritten
+    Rewritten,
+}
+
+// Edits the extension block of an existing v2/v3 file. New extensions are
+// taken out of the "container" extension so that the rest of the file can
+// stay where it is.
+
+pub struct ExtensionEditor {
+    file: String,
+    extensions: Vec<Extension>,
+    // octets between the reserved byte and the 0x0000 terminator
+    region: usize,
+}
+
+impl ExtensionEditor {
+    pub fn open(file: &str) -> Result<Self> {
+        let mut f = File::open(file)?;
+
+        let mut header = [0u8; 5];
+        read_exact(&mut f, &mut header)?;
+
+        if &header[..3] != b"AES" { return Err(Error::NotAesCrypt) }
+
+        // earlier versions have no extension block
+        if header[3] < 2 || header[3] > 3 { return Err(Error::UnsupportedVersion(header[3])) }
+
+        let all = detect::read_extensions(&mut f)?;
+        let region = all.iter().map(|e| 2 + e.size()).sum();
+
+        Ok(ExtensionEditor {
+            file: file.to_string(),
+            extensions: all.into_iter().filter(|e| !e.is_container()).collect(),
+            region,
+        })
+    }
+
+    // extensions other than the container
+    pub fn extensions(&self) -> &[Extension] {
+        &self.extensions
+    }
+
+    pub fn get(&self, identifier: &str) -> Option<&Extension> {
+        self.extensions.iter().find(|e| e.identifier == identifier)
+    }
+
+    // inserts the extension, or replaces the contents of an existing one
+    pub fn set(&mut self, identifier: &str, contents: &[u8]) -> Result<()> {
+        if identifier.is_empty() || identifier.as_bytes().contains(&0x00) {
+            return Err(Error::InvalidInput("invalid extension identifier"))
+        }
+
+        let extension = Extension::new(identifier, contents);
+
+        if extension.size() > u16::MAX as usize {
+            return Err(Error::InvalidInput("extension is too large"))
+        }
+
+        match self.extensions.iter_mut().find(|e| e.identifier == identifier) {
+            Some(existing) => *existing = extension,
+            None => self.extensions.push(extension),
+        }
+
+        Ok(())
+    }
+
+    pub fn remove(&mut self, identifier: &str) -> bool {
+        let before = self.extensions.len();
+        self.extensions.retain(|e| e.identifier != identifier);
+        before != self.extensions.len()
+    }
+
+    pub fn commit(self) -> Result<EditOutcome> {
+        let used: usize = self.extensions.iter().map(|e| 2 + e.size()).sum();
+
+        // the leftover space must be zero or able to hold a container
+        // (length field plus at least the 0x00 identifier octet)
+        if used == self.region || used + 3 <= self.region {
+            let mut bytes = encode(&self.extensions)?;
+            push_containers(&mut bytes, self.region - used)?;
+
+            let mut f = OpenOptions::new().write(true).open(&self.file)?;
+            f.seek(SeekFrom::Start(5))?;
+            f.write_all(&bytes)?;
+            f.sync_all()?;
+
+            return Ok(EditOutcome::InPlace)
+        }
+
+        self.rewrite()?;
+
+        Ok(EditOutcome::Rewritten)
+    }
+
+    fn rewrite(&self) -> Result<()> {
+        let path = Path::new(&self.file);
+
+        let mut extensions = encode(&self.extensions)?;
+        push_containers(&mut extensions, 2 + CONTAINER_SIZE)?;
+
+        replace_file(path, |mut output| {
+            let mut input = File::open(path)?;
+
+            let mut header = [0u8; 5];
+            input.read_exact(&mut header)?;
+            input.seek(SeekFrom::Current(self.region as i64))?;
+
+            output.write_all(&header)?;
+            output.write_all(&extensions)?;
+
+            // terminator, IV, key block and message are copied verbatim
+            io::copy(&mut input, &mut output)?;
+
+            Ok(output)
+        })
+    }
+}
+
+// hidden sibling of `path` that is written and then renamed over it
+pub(crate) fn temp_path(path: &Path) -> PathBuf {
+    path.with_file_name(format!(
+        ".{}.aescry-tmp",
+        path.file_name().and_then(|n| n.to_str()).unwrap_or("file")
+    ))
+}
+
+// Has `write` fill a new file at `temp_path(path)` and hand it back, then
+// syncs it, gives it the permissions of `path` and renames it over `path`.
+// The temp file is removed if any step fails.
+pub(crate) fn replace_file<F>(path: &Path, write: F) -> Result<()>
+    where F: FnOnce(File) -> Result<File>
+{
+    let temp = temp_path(path);
+
+    let result = (|| -> Result<()> {
+        let permissions = fs::metadata(path)?.permissions();
+
+        let output = write(File::create(&temp)?)?;
+        output.sync_all()?;
+        fs::set_permissions(&temp, permissions)?;
+
+        Ok(fs::rename(&temp, path)?)
+    })();
+
+    if result.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+
+    result
+}
+
+// fills `space` octets (length fields included) with container extensions
+fn push_containers(bytes: &mut Vec<u8>, mut space: usize) -> Result<()> {
+    while space > 0 {
+        let mut size = (space - 2).min(u16::MAX as usize);
+
+        // never leave a gap too small to hold another container
+        let rest = space - 2 - size;
+        if rest > 0 && rest < 3 { size -= 3; }
+
+        bytes.extend_from_slice(&encode(&[Extension::container(size)])?);
+
+        space -= 2 + size;
+    }
+
+    Ok(())
+}
+
+// Replaces the password of a v1-v3 file. Only the IV, the encrypted
+// session IV + key and its HMAC are rewritten; the message is untouched.
+// Version 0 encrypts the message with the password derived key itself,
+// so it cannot be rekeyed this way.
+pub fn rekey(file: &str, old_password: &str, new_password: &str) -> Result<()> {
+    let mut f = OpenOptions::new().read(true).write(true).open(file)?;
+
+    let info = AesHeaderInfo::from_reader(&mut f)?;
+    if info.version == 0 { return Err(Error::UnsupportedVersion(0)) }
+
+    f.seek(SeekFrom::Start(info.iv_offset))?;
+    let session = read_session(&mut f)?;
+
+    let new_iv = random_iv();
+
+    let (old_key, new_key, suffix) = match info.kdf_iterations {
+        Some(n) => (pbkdf2_sha512(old_password, &session.iv, n), pbkdf2_sha512(new_password, &new_iv, n), &[0x03][..]),
+        None => (derive_key(old_password, &session.iv), derive_key(new_password, &new_iv), &[][..]),
+    };
+
+    let (iv2, key2) = open_session(&session, &old_key, suffix)?;
+
+    let mut bytes = Vec::with_capacity(16 + 48 + 32);
+    push_wrapped_key(&mut bytes, &new_iv, &new_key, suffix, &iv2, &key2);
+
+    f.seek(SeekFrom::Start(info.iv_offset))?;
+    f.write_all(&bytes)?;
+    f.sync_all()?;
+
+    Ok(())
+}
+
+#[test]
+fn edit_in_place() {
+    let dir = Scratch::new("edit-in-place");
+    let path = dir.copy_of("hello_v2.txt.aes", "file.aes");
+    let before = fs::metadata(&path).unwrap().len();
+
+    let mut editor = ExtensionEditor::open(&path).unwrap();
+    editor.set("CREATED_DATE", b"2018-11-02").unwrap();
+    editor.set("CREATED_BY", b"aescry").unwrap();
+
+    assert_eq!(editor.commit().unwrap(), EditOutcome::InPlace);
+    assert_eq!(fs::metadata(&path).unwrap().len(), before);
+
+    let file = detect::get_file(&path).unwrap();
+    assert_eq!(file.extensions(), &[
+        Extension::new("CREATED_BY", b"aescry"),
+        Extension::new("CREATED_DATE", b"2018-11-02"),
+        Extension::container(110),
+    ]);
+    assert_eq!(file.decrypt("password").unwrap().data, b"Hello, AES Crypt!\n");
+
+    let mut editor = ExtensionEditor::open(&path).unwrap();
+    assert!(editor.remove("CREATED_DATE"));
+    assert!(!editor.remove("CREATED_DATE"));
+    assert_eq!(editor.commit().unwrap(), EditOutcome::InPlace);
+
+    let file = detect::get_file(&path).unwrap();
+    assert_eq!(file.extensions(), &[
+        Extension::new("CREATED_BY", b"aescry"),
+        Extension::container(135),
+    ]);
+}
+
+#[test]
+fn edit_overflow_rewrites() {
+    #[cfg(unix)]
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = Scratch::new("edit-rewrite");
+    let path = dir.copy_of("hello_v2.txt.aes", "file.aes");
+    let before = fs::metadata(&path).unwrap().len();
+
+    #[cfg(unix)]
+    fs::set_permissions(&path, fs::Permissions::from_mode(0o600)).unwrap();
+
+    let mut editor = ExtensionEditor::open(&path).unwrap();
+    editor.set("urn:example:blob", &[0xAB; 200]).unwrap();
+
+    assert_eq!(editor.commit().unwrap(), EditOutcome::Rewritten);
+    assert!(fs::metadata(&path).unwrap().len() > before);
+
+    // the replacement keeps the original's mode
+    #[cfg(unix)]
+    assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+
+    let file = detect::get_file(&path).unwrap();
+    assert_eq!(file.extensions(), &[
+        Extension::new("CREATED_BY", b"make_fixtures"),
+        Extension::new("urn:example:blob", &[0xAB; 200]),
+        Extension::container(128),
+    ]);
+    assert_eq!(file.decrypt("password").unwrap().data, b"Hello, AES Crypt!\n");
+}
+
+#[test]
+fn rekey_keeps_message() {
+    let dir = Scratch::new("edit-rekey");
+
+    for version in 1..4 {
+        let path = dir.copy_of(&format!("hello_v{}.txt.aes", version), &format!("v{}.aes", version));
+        let original = fs::read(&path).unwrap();
+
+        rekey(&path, "password", "n3w pässword").unwrap();
+
+        let bytes = fs::read(&path).unwrap();
+        let file = detect::get_file(&path).unwrap();
+        let info = file.header_info().unwrap();
+
+        // only the IV, key block and its HMAC differ
+        assert_eq!(bytes.len(), original.len());
+        assert_eq!(&bytes[..info.iv_offset as usize], &original[..info.iv_offset as usize]);
+        assert_eq!(&bytes[info.ciphertext_start as usize..], &original[info.ciphertext_start as usize..]);
+
+        assert!(!file.verify_password("password").unwrap());
+        assert_eq!(file.decrypt("n3w pässword").unwrap().data, b"Hello, AES Crypt!\n");
+    }
+}
+
+#[test]
+fn rekey_rejects() {
+    let dir = Scratch::new("edit-rekey-rejects");
+
+    let path = dir.copy_of("hello_v2.txt.aes", "v2.aes");
+    let original = fs::read(&path).unwrap();
+
+    assert!(matches!(rekey(&path, "wrong", "new"), Err(Error::HmacMismatch)));
+    assert_eq!(fs::read(&path).unwrap(), original);
+
+    let path = dir.copy_of("hello_v0.txt.aes", "v0.aes");
+    assert!(matches!(rekey(&path, "password", "new"), Err(Error::UnsupportedVersion(0))));
+}
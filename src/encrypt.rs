@@ -0,0 +1,496 @@
+use std::io::{self, Seek, SeekFrom, Write};
+
+use byteorder::{BigEndian, ByteOrder};
+use rand::rngs::OsRng;
+use rand::RngCore;
+
+use crate::Extension;
+use crate::extension::{encode, CONTAINER_SIZE};
+use crate::error::{Error, Result};
+use crate::crypt::{cbc_encrypt, derive_key, pbkdf2_sha512, Cbc};
+use crate::decrypt::MAX_KDF_ITERATIONS;
+use crate::sha256::{hmac_starts, hmac_update, hmac_finish, SHA256Context};
+
+const CREATED_BY: &str = concat!(env!("CARGO_PKG_NAME"), " ", env!("CARGO_PKG_VERSION"));
+
+// AES Crypt 4 default for version 3 files
+const DEFAULT_KDF_ITERATIONS: u32 = 300_000;
+
+// most plaintext taken by a single `write`
+const CHUNK_SIZE: usize = 64 * 1024;
+
+pub struct Encryptor {
+    version: u8,
+    iterations: u32,
+    created_by: String,
+}
+
+impl Encryptor {
+    pub fn new() -> Self {
+        Encryptor {
+            version: 2,
+            iterations: DEFAULT_KDF_ITERATIONS,
+            created_by: CREATED_BY.to_string(),
+        }
+    }
+
+    // 0 to 3. A version 0 header records the length of the final block, so
+    // those files are made by `encrypt`, which knows it up front, or by
+    // `seekable_writer`, which goes back and fills it in.
+    pub fn version(mut self, version: u8) -> Self {
+        self.version = version;
+        self
+    }
+
+    // PBKDF2 iteration count written to version 3 files, 1 to 5,000,000
+    pub fn iterations(mut self, iterations: u32) -> Self {
+        self.iterations = iterations;
+        self
+    }
+
+    pub fn created_by(mut self, creator: &str) -> Self {
+        self.created_by = creator.to_string();
+        self
+    }
+
+    // Writes the header and returns a writer that encrypts everything
+    // written to it; `finish` must be called to complete the file. Not
+    // available for version 0; see `seekable_writer`.
+    pub fn writer<W: Write>(&self, password: &str, output: W) -> Result<AesCryptWriter<W>> {
+        self.start(password, output, None)
+    }
+
+    // Like `writer`, but also handles version 0: the modulo octet is
+    // written as a placeholder and patched by `finish` once the message
+    // length is known.
+    pub fn seekable_writer<W: Write + Seek>(&self, password: &str, mut output: W) -> Result<SeekableAesCryptWriter<W>> {
+        let modulo_at = match self.version {
+            0 => Some(output.stream_position()? + 4),
+            _ => None,
+        };
+
+        Ok(SeekableAesCryptWriter {
+            writer: self.start(password, output, modulo_at.map(|_| 0))?,
+            modulo_at,
+        })
+    }
+
+    pub fn encrypt<W: Write>(&self, password: &str, data: &[u8], output: &mut W) -> Result<()> {
+        let mut writer = self.start(password, output, Some((data.len() % 16) as u8))?;
+
+        writer.write_all(data)?;
+        writer.finish()?;
+
+        Ok(())
+    }
+
+    // `modulo` is the message length modulo 16, needed up front by version 0
+    fn start<W: Write>(&self, password: &str, mut output: W, modulo: Option<u8>) -> Result<AesCryptWriter<W>> {
+        let mut header = Vec::with_capacity(512);
+
+        let (iv, key) = match self.version {
+            // Version 0 encrypts the message directly with the password
+            // derived key
+            0 => {
+                let modulo = modulo.ok_or(Error::InvalidInput("version 0 files need the message length up front"))?;
+
+                header.extend_from_slice(b"AES\x00");
+                header.push(modulo);
+
+                let iv = random_iv();
+                header.extend_from_slice(&iv);
+                (iv, derive_key(password, &iv))
+            },
+            1 => {
+                header.extend_from_slice(b"AES\x01\x00");
+
+                let iv1 = random_iv();
+                push_session_key(&mut header, &iv1, &derive_key(password, &iv1), &[])
+            },
+            2 => {
+                push_header(&mut header, 0x02, &self.created_by)?;
+
+                let iv1 = random_iv();
+                push_session_key(&mut header, &iv1, &derive_key(password, &iv1), &[])
+            },
+            3 => {
+                // the most a reader will accept
+                if self.iterations == 0 || self.iterations > MAX_KDF_ITERATIONS {
+                    return Err(Error::InvalidKdfIterations(self.iterations))
+                }
+
+                push_header(&mut header, 0x03, &self.created_by)?;
+
+                let mut count = [0u8; 4];
+                BigEndian::write_u32(&mut count, self.iterations);
+                header.extend_from_slice(&count);
+
+                let iv1 = random_iv();
+                let key = pbkdf2_sha512(password, &iv1, self.iterations);
+                push_session_key(&mut header, &iv1, &key, &[0x03])
+            },
+            v => return Err(Error::UnsupportedVersion(v)),
+        };
+
+        output.write_all(&header)?;
+
+        Ok(AesCryptWriter {
+            inner: output,
+            version: self.version,
+            cbc: Cbc::new(&key, &iv),
+            mac: hmac_starts(&key),
+            buffer: Vec::with_capacity(16),
+        })
+    }
+}
+
+impl Default for Encryptor {
+    fn default() -> Self { Self::new() }
+}
+
+pub(crate) fn random_iv() -> [u8; 16] {
+    let mut iv = [0u8; 16];
+    OsRng.fill_bytes(&mut iv);
+    iv
+}
+
+// Appends the IV, encrypted block and HMAC for a random session IV + key;
+// returns the session pair.
+
+fn push_session_key(bytes: &mut Vec<u8>, iv1: &[u8; 16], key: &[u8; 32], suffix: &[u8]) -> ([u8; 16], [u8; 32]) {
+    let iv2 = random_iv();
+    let mut key2 = [0u8; 32];
+
+    OsRng.fill_bytes(&mut key2);
+
+    push_wrapped_key(bytes, iv1, key, suffix, &iv2, &key2);
+
+    (iv2, key2)
+}
+
+// Encrypts the session IV + key with the password derived key and appends
+// the IV, encrypted block and its HMAC (taken over the block and `suffix`).
+
+pub(crate) fn push_wrapped_key(bytes: &mut Vec<u8>, iv1: &[u8; 16], key: &[u8; 32], suffix: &[u8], iv2: &[u8; 16], key2: &[u8; 32]) {
+    let mut plain = [0u8; 48];
+    plain[..16].copy_from_slice(iv2);
+    plain[16..].copy_from_slice(key2);
+
+    let block = cbc_encrypt(key, iv1, &plain);
+
+    let mut digest = [0u8; 32];
+    let mut ctx = hmac_starts(key);
+
+    hmac_update(&mut ctx, &block);
+    hmac_update(&mut ctx, suffix);
+    hmac_finish(&mut ctx, &mut digest);
+
+    bytes.extend_from_slice(iv1);
+    bytes.extend_from_slice(&block);
+    bytes.extend_from_slice(&digest);
+}
+
+fn push_header(bytes: &mut Vec<u8>, version: u8, created_by: &str) -> Result<()> {
+    let extensions = encode(&[
+        Extension::new("CREATED_BY", created_by.as_bytes()),
+        Extension::container(CONTAINER_SIZE),
+    ])?;
+
+    bytes.extend_from_slice(b"AES");
+    bytes.push(version);
+    bytes.push(0x00);
+
+    bytes.extend_from_slice(&extensions);
+    bytes.extend_from_slice(&[0x00, 0x00]);
+
+    Ok(())
+}
+
+// Encrypts a message as it is written. Whole blocks are passed straight
+// through to the underlying writer; the final partial block, the modulo
+// octet and the closing HMAC are written by `finish`.
+
+pub struct AesCryptWriter<W: Write> {
+    inner: W,
+    version: u8,
+    cbc: Cbc,
+    mac: SHA256Context,
+    // plaintext short of a full block
+    buffer: Vec<u8>,
+}
+
+impl<W: Write> AesCryptWriter<W> {
+    // a version 2 writer; see `Encryptor::writer` for other options
+    pub fn new(inner: W, password: &str) -> Result<Self> {
+        Encryptor::new().writer(password, inner)
+    }
+
+    pub fn finish(self) -> Result<W> {
+        self.complete().map(|(inner, _)| inner)
+    }
+
+    // writes the final block and HMAC; returns the writer and the modulo
+    fn complete(mut self) -> Result<(W, u8)> {
+        let modulo = self.buffer.len();
+        let mut last = std::mem::take(&mut self.buffer);
+
+        if self.version == 3 {
+            // PKCS#7 padding
+            let pad = 16 - modulo;
+            last.resize(modulo + pad, pad as u8);
+        } else if modulo != 0 {
+            // the final partial block is zero filled
+            last.resize(16, 0x00);
+        }
+
+        self.cbc.encrypt(&mut last);
+        hmac_update(&mut self.mac, &last);
+
+        if self.version == 1 || self.version == 2 {
+            last.push(modulo as u8);
+        }
+
+        let mut digest = [0u8; 32];
+        hmac_finish(&mut self.mac, &mut digest);
+        last.extend_from_slice(&digest);
+
+        self.inner.write_all(&last)?;
+        self.inner.flush()?;
+
+        Ok((self.inner, modulo as u8))
+    }
+}
+
+impl<W: Write> Write for AesCryptWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // takes up to CHUNK_SIZE octets; `write_all` comes back for the rest
+        let buf = &buf[..buf.len().min(CHUNK_SIZE)];
+        self.buffer.extend_from_slice(buf);
+
+        let n = self.buffer.len() / 16 * 16;
+
+        if n > 0 {
+            let mut blocks: Vec<u8> = self.buffer.drain(..n).collect();
+
+            self.cbc.encrypt(&mut blocks);
+            hmac_update(&mut self.mac, &blocks);
+
+            self.inner.write_all(&blocks)?;
+        }
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+// An `AesCryptWriter` over seekable output, made by
+// `Encryptor::seekable_writer`. For version 0 `finish` goes back to the
+// header to record the length of the final block.
+
+pub struct SeekableAesCryptWriter<W: Write + Seek> {
+    writer: AesCryptWriter<W>,
+    // offset of the version 0 modulo octet
+    modulo_at: Option<u64>,
+}
+
+impl<W: Write + Seek> SeekableAesCryptWriter<W> {
+    pub fn finish(self) -> Result<W> {
+        let (mut inner, modulo) = self.writer.complete()?;
+
+        if let Some(at) = self.modulo_at {
+            inner.seek(SeekFrom::Start(at))?;
+            inner.write_all(&[modulo])?;
+            inner.seek(SeekFrom::End(0))?;
+            inner.flush()?;
+        }
+
+        Ok(inner)
+    }
+}
+
+impl<W: Write + Seek> Write for SeekableAesCryptWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.writer.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+#[cfg(test)]
+fn encrypt_to_vec(encryptor: Encryptor, data: &[u8]) -> Vec<u8> {
+    let mut bytes = vec![];
+    encryptor.encrypt("password", data, &mut bytes).unwrap();
+    bytes
+}
+
+#[test]
+fn encrypt_v2_round_trip() {
+    for len in &[0, 1, 15, 16, 17, 100] {
+        let data: Vec<u8> = (0..*len).map(|i| i as u8).collect();
+        let bytes = encrypt_to_vec(Encryptor::new(), &data);
+
+        assert_eq!(crate::decrypt::decrypt(&bytes, "password").unwrap(), data);
+    }
+}
+
+#[test]
+fn encrypt_v2_layout() {
+    let bytes = encrypt_to_vec(Encryptor::new().created_by("aescry test"), b"abc");
+
+    assert_eq!(&bytes[..5], b"AES\x02\x00");
+    assert_eq!(&bytes[5..7], &[0x00, 0x16]);
+    assert_eq!(&bytes[7..29], b"CREATED_BY\x00aescry test");
+    assert_eq!(&bytes[29..31], &[0x00, 0x80]);
+    assert!(bytes[31..159].iter().all(|b| *b == 0));
+    assert_eq!(&bytes[159..161], &[0x00, 0x00]);
+
+    // IV, key block, HMAC, one block of ciphertext, modulo, HMAC
+    assert_eq!(bytes.len(), 161 + 16 + 48 + 32 + 16 + 1 + 32);
+    assert_eq!(bytes[161 + 16 + 48 + 32 + 16], 3);
+}
+
+#[test]
+fn encrypt_v3_round_trip() {
+    for len in &[0, 1, 15, 16, 17, 100] {
+        let data: Vec<u8> = (0..*len).map(|i| i as u8).collect();
+        let bytes = encrypt_to_vec(Encryptor::new().version(3).iterations(10), &data);
+
+        assert_eq!(&bytes[..5], b"AES\x03\x00");
+        assert_eq!(crate::decrypt::v3_iterations(&mut &bytes[..]).unwrap(), 10);
+        assert_eq!(crate::decrypt::decrypt(&bytes, "password").unwrap(), data);
+    }
+}
+
+#[test]
+fn encryptor_rejects_unknown_version() {
+    let mut out = vec![];
+
+    assert!(Encryptor::new().version(9).encrypt("password", b"abc", &mut out).is_err());
+    assert!(out.is_empty());
+}
+
+#[test]
+fn encryptor_rejects_iteration_counts() {
+    for &count in &[0, MAX_KDF_ITERATIONS + 1] {
+        let mut out = vec![];
+
+        match Encryptor::new().version(3).iterations(count).encrypt("password", b"abc", &mut out) {
+            Err(Error::InvalidKdfIterations(n)) => assert_eq!(n, count),
+            other => panic!("unexpected {:?}", other),
+        }
+        assert!(out.is_empty());
+    }
+}
+
+#[test]
+fn encryptor_rejects_oversized_creator() {
+    let mut out = vec![];
+    let creator = "x".repeat(70_000);
+
+    match Encryptor::new().created_by(&creator).encrypt("password", b"abc", &mut out) {
+        Err(Error::InvalidInput(_)) => (),
+        other => panic!("unexpected {:?}", other),
+    }
+    assert!(out.is_empty());
+}
+
+#[test]
+fn writer_streams_in_pieces() {
+    let data: Vec<u8> = (0..1000).map(|i| (i * 7) as u8).collect();
+
+    for version in 1..4 {
+        let encryptor = Encryptor::new().version(version).iterations(10);
+        let mut writer = encryptor.writer("password", vec![]).unwrap();
+
+        for piece in data.chunks(7) {
+            writer.write_all(piece).unwrap();
+        }
+
+        let bytes = writer.finish().unwrap();
+
+        let mut plain = vec![];
+        let mut reader = crate::AesCryptReader::new(&bytes[..], "password").unwrap();
+        io::Read::read_to_end(&mut reader, &mut plain).unwrap();
+
+        assert_eq!(plain, data);
+    }
+}
+
+#[test]
+fn writer_refuses_version_0() {
+    match Encryptor::new().version(0).writer("password", vec![]) {
+        Err(Error::InvalidInput(_)) => (),
+        Err(e) => panic!("unexpected {:?}", e),
+        Ok(_) => panic!("version 0 writer created"),
+    }
+}
+
+#[test]
+fn seekable_writer_streams_every_version() {
+    for version in 0..4 {
+        for len in &[0, 1, 15, 16, 17, 100] {
+            let data: Vec<u8> = (0..*len).map(|i| (i * 7) as u8).collect();
+
+            // output that already holds something, so offsets are relative
+            let mut output = io::Cursor::new(b"prefix".to_vec());
+            output.seek(SeekFrom::End(0)).unwrap();
+
+            let encryptor = Encryptor::new().version(version).iterations(10);
+            let mut writer = encryptor.seekable_writer("password", output).unwrap();
+
+            for piece in data.chunks(7) {
+                writer.write_all(piece).unwrap();
+            }
+
+            let bytes = writer.finish().unwrap().into_inner();
+
+            assert_eq!(&bytes[..6], b"prefix");
+            if version == 0 { assert_eq!(bytes[6 + 4] as usize, len % 16); }
+            assert_eq!(crate::decrypt::decrypt(&bytes[6..], "password").unwrap(), data);
+        }
+    }
+}
+
+#[test]
+fn writer_bounds_large_writes() {
+    let data: Vec<u8> = (0..3 * CHUNK_SIZE + 5).map(|i| (i * 13) as u8).collect();
+
+    let mut writer = Encryptor::new().writer("password", vec![]).unwrap();
+    assert_eq!(writer.write(&data).unwrap(), CHUNK_SIZE);
+    writer.write_all(&data[CHUNK_SIZE..]).unwrap();
+
+    let bytes = writer.finish().unwrap();
+    assert_eq!(crate::decrypt::decrypt(&bytes, "password").unwrap(), data);
+}
+
+#[test]
+fn encrypt_legacy_round_trip() {
+    for version in &[0, 1] {
+        for len in &[0, 1, 15, 16, 17, 100] {
+            let data: Vec<u8> = (0..*len).map(|i| i as u8).collect();
+            let bytes = encrypt_to_vec(Encryptor::new().version(*version), &data);
+
+            assert_eq!(&bytes[..4], &[b'A', b'E', b'S', *version]);
+            assert_eq!(crate::decrypt::decrypt(&bytes, "password").unwrap(), data);
+        }
+    }
+}
+
+#[test]
+fn encrypt_legacy_layout() {
+    // IV, one block of ciphertext, HMAC; the header holds the modulo octet
+    let bytes = encrypt_to_vec(Encryptor::new().version(0), b"abc");
+    assert_eq!(bytes.len(), 5 + 16 + 16 + 32);
+    assert_eq!(bytes[4], 3);
+
+    // IV, key block, HMAC, one block of ciphertext, modulo, HMAC
+    let bytes = encrypt_to_vec(Encryptor::new().version(1), b"abc");
+    assert_eq!(&bytes[..5], b"AES\x01\x00");
+    assert_eq!(bytes.len(), 5 + 16 + 48 + 32 + 16 + 1 + 32);
+    assert_eq!(bytes[5 + 16 + 48 + 32 + 16], 3);
+}
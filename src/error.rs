@@ -0,0 +1,82 @@
+use std::error;
+use std::fmt;
+use std::io;
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    // the input does not start with the 'AES' signature
+    NotAesCrypt,
+    UnsupportedVersion(u8),
+    // a structure needed `expected` octets but only `actual` were available
+    Truncated { expected: u64, actual: u64 },
+    MalformedExtension,
+    InvalidKdfIterations(u32),
+    InvalidPadding,
+    // the password is incorrect or the file has been altered
+    HmacMismatch,
+    InvalidInput(&'static str),
+}
+
+pub type Result<T> = ::std::result::Result<T, Error>;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Io(ref e) => e.fmt(f),
+            Error::NotAesCrypt => write!(f, "not an AES Crypt file"),
+            Error::UnsupportedVersion(v) => write!(f, "unsupported AES Crypt version {}", v),
+            Error::Truncated { expected, actual } =>
+                write!(f, "file is truncated: expected {} octets, found {}", expected, actual),
+            Error::MalformedExtension => write!(f, "malformed extension"),
+            Error::InvalidKdfIterations(n) => write!(f, "invalid KDF iteration count {}", n),
+            Error::InvalidPadding => write!(f, "invalid padding"),
+            Error::HmacMismatch => write!(f, "message has been altered or password is incorrect"),
+            Error::InvalidInput(msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            Error::Io(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+// `Read`/`Write` implementations have to report through `io::Error`, so
+// crate errors are carried inside one and recovered on the way back out.
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        match e.get_ref().map(|inner| inner.is::<Error>()) {
+            Some(true) => *e.into_inner().unwrap().downcast::<Error>().unwrap(),
+            _ => Error::Io(e),
+        }
+    }
+}
+
+impl From<Error> for io::Error {
+    fn from(e: Error) -> Self {
+        match e {
+            Error::Io(e) => e,
+            Error::Truncated { .. } => io::Error::new(io::ErrorKind::UnexpectedEof, e),
+            Error::InvalidInput(_) => io::Error::new(io::ErrorKind::InvalidInput, e),
+            _ => io::Error::new(io::ErrorKind::InvalidData, e),
+        }
+    }
+}
+
+#[test]
+fn error_survives_io_round_trip() {
+    let e: io::Error = Error::UnsupportedVersion(7).into();
+
+    assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+
+    match Error::from(e) {
+        Error::UnsupportedVersion(7) => (),
+        other => panic!("unexpected {:?}", other),
+    }
+}
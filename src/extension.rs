@@ -0,0 +1,126 @@
+use std::str;
+
+use byteorder::{BigEndian, ByteOrder};
+
+use crate::error::{Error, Result};
+
+// size of the empty container written into new and rewritten headers
+pub(crate) const CONTAINER_SIZE: usize = 128;
+
+// A single entry of the v2/v3 extension block: a NUL-terminated identifier
+// followed by free-form contents. The "container" extension reserved for
+// later additions has an empty identifier.
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Extension {
+    pub identifier: String,
+    pub contents: Vec<u8>,
+}
+
+impl Extension {
+    pub fn new(identifier: &str, contents: &[u8]) -> Self {
+        Extension { identifier: identifier.to_string(), contents: contents.to_vec() }
+    }
+
+    // `size` is the full extension length, including the leading 0x00
+    pub fn container(size: usize) -> Self {
+        Extension { identifier: String::new(), contents: vec![0u8; size.max(1) - 1] }
+    }
+
+    pub fn is_container(&self) -> bool {
+        self.identifier.is_empty()
+    }
+
+    // number of octets the extension occupies after its length field
+    pub fn size(&self) -> usize {
+        self.identifier.len() + 1 + self.contents.len()
+    }
+
+    pub(crate) fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let nul = bytes.iter().position(|b| *b == 0x00).ok_or(Error::MalformedExtension)?;
+
+        let identifier = str::from_utf8(&bytes[..nul]).map_err(|_| Error::MalformedExtension)?;
+
+        Ok(Extension { identifier: identifier.to_string(), contents: bytes[nul + 1..].to_vec() })
+    }
+
+    pub(crate) fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(self.size());
+
+        bytes.extend_from_slice(self.identifier.as_bytes());
+        bytes.push(0x00);
+        bytes.extend_from_slice(&self.contents);
+
+        bytes
+    }
+}
+
+// The extensions as laid out in the header, each behind its 2 octet
+// length field. Fails if one is too large for that field.
+pub(crate) fn encode(extensions: &[Extension]) -> Result<Vec<u8>> {
+    let mut bytes = vec![];
+
+    for extension in extensions {
+        if extension.size() > u16::MAX as usize {
+            return Err(Error::InvalidInput("extension is too large"))
+        }
+
+        let mut ext_len = [0u8; 2];
+        BigEndian::write_u16(&mut ext_len, extension.size() as u16);
+
+        bytes.extend_from_slice(&ext_len);
+        bytes.extend_from_slice(&extension.to_bytes());
+    }
+
+    Ok(bytes)
+}
+
+#[test]
+fn extension_bytes_round_trip() {
+    let ext = Extension::new("CREATED_BY", b"aescry");
+    let bytes = ext.to_bytes();
+
+    assert_eq!(bytes, b"CREATED_BY\x00aescry");
+    assert_eq!(ext.size(), bytes.len());
+    assert_eq!(Extension::from_bytes(&bytes).unwrap(), ext);
+}
+
+#[test]
+fn container_extension() {
+    let ext = Extension::from_bytes(&[0u8; 128]).unwrap();
+
+    assert!(ext.is_container());
+    assert_eq!(ext, Extension::container(128));
+    assert_eq!(ext.size(), 128);
+}
+
+#[test]
+fn unterminated_identifier() {
+    assert!(Extension::from_bytes(b"CREATED_BY").is_err());
+}
+
+#[test]
+fn extensions_from_detected_file() {
+    let file = crate::detect::get_file("tests/fixtures/hello_v2.txt.aes").unwrap();
+
+    assert_eq!(file.extensions(), &[
+        Extension::new("CREATED_BY", b"make_fixtures"),
+        Extension::container(128),
+    ]);
+
+    let file = crate::detect::get_file("tests/fixtures/hello_v1.txt.aes").unwrap();
+
+    assert!(file.extensions().is_empty());
+}
+
+#[test]
+fn encode_extensions() {
+    let bytes = encode(&[Extension::new("A", b"bc"), Extension::container(3)]).unwrap();
+    assert_eq!(bytes, b"\x00\x04A\x00bc\x00\x03\x00\x00\x00");
+
+    let oversized = Extension::new("CREATED_BY", &vec![b'x'; 65_525]);
+    match encode(&[oversized]) {
+        Err(Error::InvalidInput(_)) => (),
+        other => panic!("unexpected {:?}", other),
+    }
+}
@@ -0,0 +1,207 @@
+use std::io::{Cursor, Read, Seek, SeekFrom};
+
+use crate::{detect, AesFile, Extension};
+use crate::error::{Error, Result};
+#[cfg(test)]
+use crate::testing::fixture;
+
+// Where one extension sits in the file. `offset` points at the 2 octet
+// length field and `length` is the value stored there.
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ExtensionSpan {
+    pub offset: u64,
+    pub length: u16,
+    pub extension: Extension,
+}
+
+// Layout of an AES Crypt file as read from its header, without a password.
+// Offsets are from the start of the file; the optional fields are absent
+// in versions that lack the corresponding section.
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct AesHeaderInfo {
+    pub version: u8,
+    pub extensions: Vec<ExtensionSpan>,
+    // octets held by container extensions, length fields included
+    pub container_capacity: u64,
+    pub kdf_iterations: Option<u32>,
+    pub iv_offset: u64,
+    pub key_block_offset: Option<u64>,
+    pub key_hmac_offset: Option<u64>,
+    pub ciphertext_start: u64,
+    pub ciphertext_end: u64,
+    pub modulo_offset: Option<u64>,
+    pub hmac_offset: u64,
+    pub file_size: u64,
+}
+
+impl AesHeaderInfo {
+    // Reads the header from the current position of `reader` and seeks to
+    // the end to find the file size.
+    pub fn from_reader<R: Read + Seek>(mut reader: R) -> Result<Self> {
+        let base = reader.stream_position()?;
+        let file = detect::from_reader(&mut reader)?;
+        let end = reader.seek(SeekFrom::End(0))?;
+
+        layout(&file, end - base)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        Self::from_reader(Cursor::new(bytes))
+    }
+}
+
+fn layout(file: &AesFile, file_size: u64) -> Result<AesHeaderInfo> {
+    let mut offset = 5;
+    let mut extensions = vec![];
+    let mut container_capacity = 0;
+
+    for extension in &file.extensions {
+        let length = extension.size() as u64;
+
+        if extension.is_container() { container_capacity += 2 + length }
+
+        extensions.push(ExtensionSpan { offset, length: length as u16, extension: extension.clone() });
+        offset += 2 + length;
+    }
+
+    // extension terminator, then the iteration count in version 3
+    match file.version {
+        2 => offset += 2,
+        3 => offset += 2 + 4,
+        _ => (),
+    }
+
+    let iv_offset = offset;
+    let ciphertext_start = iv_offset + key_section_size(file.version);
+
+    let (key_block_offset, key_hmac_offset) = match file.version {
+        0 => (None, None),
+        _ => (Some(iv_offset + 16), Some(iv_offset + 16 + 48)),
+    };
+
+    let trailer = trailer_size(file.version);
+
+    if file_size < ciphertext_start + trailer {
+        return Err(Error::Truncated { expected: ciphertext_start + trailer, actual: file_size })
+    }
+
+    let ciphertext_end = file_size - trailer;
+
+    let modulo_offset = match file.version {
+        0 => Some(4),
+        1 | 2 => Some(ciphertext_end),
+        _ => None,
+    };
+
+    Ok(AesHeaderInfo {
+        version: file.version,
+        extensions,
+        container_capacity,
+        kdf_iterations: file.kdf_iterations,
+        iv_offset,
+        key_block_offset,
+        key_hmac_offset,
+        ciphertext_start,
+        ciphertext_end,
+        modulo_offset,
+        hmac_offset: file_size - 32,
+        file_size,
+    })
+}
+
+// Octets from the IV to the ciphertext: the IV itself and, from version 1
+// on, the encrypted session IV + key and its HMAC.
+pub(crate) fn key_section_size(version: u8) -> u64 {
+    match version {
+        0 => 16,
+        _ => 16 + 48 + 32,
+    }
+}
+
+// Octets after the ciphertext: the modulo octet (v1, v2) and the closing
+// HMAC.
+pub(crate) fn trailer_size(version: u8) -> u64 {
+    match version {
+        1 | 2 => 33,
+        _ => 32,
+    }
+}
+
+#[cfg(test)]
+fn fixture_info(name: &str) -> AesHeaderInfo {
+    AesHeaderInfo::from_bytes(&fixture(name)).unwrap()
+}
+
+#[test]
+fn layout_v0() {
+    let info = fixture_info("hello_v0.txt.aes");
+
+    assert_eq!(info.version, 0);
+    assert_eq!(info.modulo_offset, Some(4));
+    assert_eq!(info.iv_offset, 5);
+    assert_eq!(info.key_block_offset, None);
+    assert_eq!((info.ciphertext_start, info.ciphertext_end), (21, 53));
+    assert_eq!((info.hmac_offset, info.file_size), (53, 85));
+}
+
+#[test]
+fn layout_v1() {
+    let info = fixture_info("hello_v1.txt.aes");
+
+    assert!(info.extensions.is_empty());
+    assert_eq!(info.iv_offset, 5);
+    assert_eq!(info.key_block_offset, Some(21));
+    assert_eq!(info.key_hmac_offset, Some(69));
+    assert_eq!((info.ciphertext_start, info.ciphertext_end), (101, 133));
+    assert_eq!(info.modulo_offset, Some(133));
+    assert_eq!((info.hmac_offset, info.file_size), (134, 166));
+}
+
+#[test]
+fn layout_v2() {
+    let info = fixture_info("hello_v2.txt.aes");
+
+    // "CREATED_BY\0make_fixtures" and a 128 octet container
+    assert_eq!(info.extensions.len(), 2);
+    assert_eq!((info.extensions[0].offset, info.extensions[0].length), (5, 24));
+    assert_eq!(info.extensions[0].extension.identifier, "CREATED_BY");
+    assert_eq!((info.extensions[1].offset, info.extensions[1].length), (31, 128));
+    assert_eq!(info.container_capacity, 130);
+
+    assert_eq!(info.iv_offset, 163);
+    assert_eq!(info.key_block_offset, Some(179));
+    assert_eq!((info.ciphertext_start, info.ciphertext_end), (259, 291));
+    assert_eq!((info.hmac_offset, info.file_size), (292, 324));
+}
+
+#[test]
+fn layout_v3() {
+    let info = fixture_info("hello_v3.txt.aes");
+
+    assert_eq!(info.kdf_iterations, Some(1000));
+    assert_eq!(info.iv_offset, 167);
+    assert_eq!((info.ciphertext_start, info.ciphertext_end), (263, 295));
+    assert_eq!(info.modulo_offset, None);
+    assert_eq!((info.hmac_offset, info.file_size), (295, 327));
+}
+
+#[test]
+fn layout_from_detected_file() {
+    let file = detect::get_file("tests/fixtures/aligned_v2.txt.aes").unwrap();
+    let info = file.header_info().unwrap();
+
+    assert_eq!(info.ciphertext_end - info.ciphertext_start, 64);
+    assert_eq!(info.file_size, 356);
+}
+
+#[test]
+fn layout_truncated() {
+    let bytes = fixture("hello_v1.txt.aes");
+
+    match AesHeaderInfo::from_bytes(&bytes[..120]) {
+        Err(Error::Truncated { expected: 134, actual: 120 }) => (),
+        other => panic!("unexpected {:?}", other),
+    }
+}
@@ -1,6 +1,25 @@
 #![allow(unused_imports, dead_code, unused_variables, unused_assignments, unused_macros)]
-#![feature(fixed_size_array)]
-#![feature(min_const_fn)]
+#![allow(clippy::needless_range_loop, clippy::too_many_arguments)]
+// ---------------------- Version 3 ------------------------
+//
+//   3 Octets - 'AES'
+//   1 Octet  - 0x03 (Version)
+//   1 Octet  - Reserved (set to 0x00)
+//  .... Repeating extension block section, as in version 2
+//   4 Octets - Number of KDF iterations (in network byte order)
+//  16 Octets - Public Initialization Vector (IV), also the salt for
+//              PBKDF2-HMAC-SHA512 over the UTF-8 password
+//  48 Octets - Encrypted IV and 256-bit AES key used to encrypt the
+//              bulk of the file
+//              16 octets - initialization vector
+//              32 octets - encryption key
+//  32 Octets - HMAC of the encrypted IV and key followed by the
+//              version octet
+//  nn Octets - Encrypted message, PKCS#7 padded
+//  32 Octets - HMAC
+//
+//  Thus, the footprint of the file is at least 155 octets.
+//
 // ---------------------- Version 2 ------------------------
 //
 //   3 Octets - 'AES'
@@ -92,8 +111,10 @@
 type BytesProcessed = usize;
 
 extern crate byteorder;
+extern crate glob;
+extern crate rand;
 use byteorder::{BigEndian,ByteOrder};
-use std::io::prelude::*;
+use std::io::{self, prelude::*};
 use std::fs::File;
 use std::str;
 
@@ -102,11 +123,37 @@ mod fixed_tables;
 
 mod algorithms;
 mod aes;
+mod carve;
+#[cfg(test)]
+mod cavp;
+mod crypt;
+mod decrypt;
+mod edit;
+mod encrypt;
+mod error;
+mod extension;
+mod header;
+mod scan;
 mod sha256;
+mod sha512;
+#[cfg(test)]
+mod testing;
+mod upgrade;
 mod util;
+mod validate;
 use crate::sha256::*;
 
-pub struct Extension;
+pub use crate::aes::{Aes128, Aes192, Aes256, BlockCipher};
+pub use crate::carve::{Carved, Carver, Extent};
+pub use crate::decrypt::{verify, AesCryptReader, Verification};
+pub use crate::edit::{rekey, EditOutcome, ExtensionEditor};
+pub use crate::encrypt::{AesCryptWriter, Encryptor, SeekableAesCryptWriter};
+pub use crate::error::{Error, Result};
+pub use crate::extension::Extension;
+pub use crate::header::{AesHeaderInfo, ExtensionSpan};
+pub use crate::sha256::Sha256;
+pub use crate::upgrade::{upgrade, upgrade_dir, UpgradeEntry};
+pub use crate::validate::{Confidence, Validation, ValidationIssue};
 
 pub struct AesFileData {
     pub version: u8,
@@ -114,85 +161,298 @@ pub struct AesFileData {
     pub data: Vec<u8>,
 }
 
+#[derive(Debug)]
 pub struct AesFile {
     version: u8,
-    file: String,
+    file: Option<String>,
+    extensions: Vec<Extension>,
+    kdf_iterations: Option<u32>,
 }
 
 impl AesFile {
     pub fn new(v: u8, file: &str) -> Self {
-        AesFile {version: v, file: file.to_string()}
+        AesFile {version: v, file: Some(file.to_string()), extensions: vec![], kdf_iterations: None}
+    }
+
+    pub fn version(&self) -> u8 {
+        self.version
+    }
+
+    // path the file was detected from; `None` for readers and byte slices
+    pub fn path(&self) -> Option<&str> {
+        self.file.as_deref()
+    }
+
+    // extensions from the v2/v3 header, including any container
+    pub fn extensions(&self) -> &[Extension] {
+        &self.extensions
+    }
+
+    // PBKDF2 iteration count from a version 3 header
+    pub fn kdf_iterations(&self) -> Option<u32> {
+        self.kdf_iterations
+    }
+
+    // layout of the file on disk; see `AesHeaderInfo::from_reader` for other sources
+    pub fn header_info(&self) -> Result<AesHeaderInfo> {
+        AesHeaderInfo::from_reader(io::BufReader::new(self.open()?))
+    }
+
+    // structural checks beyond the signature; see `Validation`
+    pub fn validate(&self) -> Result<Validation> {
+        Validation::from_reader(io::BufReader::new(self.open()?))
+    }
+
+    // Checks the password without decrypting the message. For versions 1
+    // to 3 only the header is read; version 0 files are read in full.
+    pub fn verify_password(&self, password: &str) -> Result<bool> {
+        decrypt::check_password(io::BufReader::new(self.open()?), password)
+    }
+
+    // integrity check of the whole file that writes no plaintext; see `verify`
+    pub fn verify(&self, password: &str) -> Result<Verification> {
+        verify(io::BufReader::new(self.open()?), password)
+    }
+
+    // changes the password in place; see `rekey`
+    pub fn rekey(&self, old_password: &str, new_password: &str) -> Result<()> {
+        match self.file {
+            Some(ref path) => rekey(path, old_password, new_password),
+            None => Err(Error::InvalidInput("AES file was not detected from a path")),
+        }
+    }
+
+    pub fn decrypt(&self, password: &str) -> Result<AesFileData> {
+        let f = io::BufReader::new(self.open()?);
+
+        let mut data = vec![];
+        AesCryptReader::new(f, password)?.read_to_end(&mut data)?;
+
+        Ok(AesFileData { version: self.version, extensions: self.extensions.clone(), data })
+    }
+
+    fn open(&self) -> Result<File> {
+        match self.file {
+            Some(ref path) => Ok(File::open(path)?),
+            None => Err(Error::InvalidInput("AES file was not detected from a path")),
+        }
     }
 }
 
 pub mod detect {
     use super::*;
+    use crate::decrypt::{read_exact, read_iterations};
+    #[cfg(test)]
+    use crate::testing::{fixture, Scratch};
+
+    pub use crate::scan::{scan_dir, scan_dir_parallel, ParallelScan, ScanDir, ScanEntry, ScanOptions, ScanStats, SymlinkPolicy};
+
+    pub fn get_file(file: &str) -> Result<AesFile> {
+        let f = io::BufReader::new(File::open(file)?);
+
+        let mut aes_file = from_reader(f)?;
+        aes_file.file = Some(file.to_string());
+
+        Ok(aes_file)
+    }
 
-    pub fn get_file(file: &str) -> Option<AesFile> {
-        let mut f = File::open(file).unwrap();
-        if is_aes_header(&mut f).is_err() { return None }
-        match byte_as_version(&mut f) {
-            2 => Some(AesFile::new(2, file)),
-            1 => Some(AesFile::new(1, file)),
-            0 => Some(AesFile::new(0, file)),
-            _ => None,
+    // Reads the header from the current position of `reader`, consuming it
+    // up to the end of the extensions (and the KDF iteration count for v3).
+    pub fn from_reader<R: Read>(mut reader: R) -> Result<AesFile> {
+        let f = &mut reader;
+        is_aes_header(f)?;
+        let version = match byte_as_version(f)? {
+            v @ 0..=3 => v,
+            v => return Err(Error::UnsupportedVersion(v)),
+        };
+
+        let mut aes_file = AesFile { version, file: None, extensions: vec![], kdf_iterations: None };
+
+        if aes_file.version >= 2 {
+            skip_byte(f)?;
+            aes_file.extensions = read_extensions(f)?;
         }
+
+        if aes_file.version == 3 {
+            aes_file.kdf_iterations = Some(read_iterations(f)?);
+        }
+
+        Ok(aes_file)
     }
 
-    fn is_aes_header(file: &mut File) -> Result<(), ()> {
+    pub fn from_bytes(bytes: &[u8]) -> Result<AesFile> {
+        from_reader(bytes)
+    }
+
+    fn is_aes_header<R: Read>(file: &mut R) -> Result<()> {
         let mut aes = [0u8; 3];
+        let mut read = 0;
 
-        if file.read_exact(&mut aes).is_err() {
-            return Err(());
+        while read < aes.len() {
+            match file.read(&mut aes[read..]) {
+                Ok(0) => break,
+                Ok(n) => read += n,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e.into()),
+            }
         }
 
-        if &aes == b"AES" { Ok(()) } else { Err(()) }
+        // a short file that still reads as the start of 'AES' was cut off
+        if read == 0 || aes[..read] != b"AES"[..read] { return Err(Error::NotAesCrypt) }
+        if read < aes.len() {
+            return Err(Error::Truncated { expected: aes.len() as u64, actual: read as u64 })
+        }
+
+        Ok(())
     }
 
-    fn byte_as_version(file: &mut File) -> u8 {
+    fn byte_as_version<R: Read>(file: &mut R) -> Result<u8> {
         let mut version = [0; 1];
-        file.read_exact(&mut version).unwrap();
-        version[0]
+        read_exact(file, &mut version)?;
+        Ok(version[0])
     }
 
-    fn skip_byte(file: &mut File) {
+    fn skip_byte<R: Read>(file: &mut R) -> Result<()> {
       let mut b = [0; 1];
-      file.read_exact(&mut b).unwrap();
+      read_exact(file, &mut b)
     }
 
-    fn read_extension_length_from_bytes(file: &mut File) -> u16 {
+    fn read_extension_length_from_bytes<R: Read>(file: &mut R) -> Result<u16> {
       let mut ext_len = [0; 2];
-      file.read_exact(&mut ext_len).unwrap();
-      BigEndian::read_u16(&ext_len)
+      read_exact(file, &mut ext_len)?;
+      Ok(BigEndian::read_u16(&ext_len))
+    }
+
+    fn read_extension_contents<R: Read>(file: &mut R, length: usize) -> Result<Vec<u8>> {
+      let mut buf = vec![0; length];
+      read_exact(file, &mut buf)?;
+      Ok(buf)
     }
 
-    fn read_extension_contents(file: &mut File, length: usize) -> String {
-      let mut buf = vec![0; length].into_boxed_slice();
-      file.read_exact(&mut buf).unwrap();
-      str::from_utf8(&buf).unwrap().to_string()
+    fn is_uninitialized(s: &[u8]) -> bool {
+      s.iter().all(|v| *v == 0x00)
     }
 
-    fn is_uninitialized(s: &str) -> bool {
-      s.chars().all(|v| v == '\u{0}')
+    // walks the repeating extension block up to its 0x0000 terminator
+    pub(crate) fn read_extensions<R: Read>(file: &mut R) -> Result<Vec<Extension>> {
+      let mut extensions = vec![];
+
+      loop {
+        let length = read_extension_length_from_bytes(file)?;
+        if length == 0 { return Ok(extensions) }
+
+        let contents = read_extension_contents(file, length as usize)?;
+
+        if is_uninitialized(&contents) {
+          extensions.push(Extension::container(contents.len()));
+        } else {
+          extensions.push(Extension::from_bytes(&contents)?);
+        }
+      }
     }
 
-    fn read_iv1(file: &mut File) -> [u8; 16] {
+    fn read_iv1<R: Read>(file: &mut R) -> Result<[u8; 16]> {
       let mut iv = [0; 16];
-      file.read_exact(&mut iv).unwrap();
-      iv
+      read_exact(file, &mut iv)?;
+      Ok(iv)
     }
 
-    fn read_iv_and_key(file: &mut File) -> ([u8; 16], [u8; 32]) {
+    fn read_iv_and_key<R: Read>(file: &mut R) -> Result<([u8; 16], [u8; 32])> {
       let mut iv = [0; 16];
       let mut key = [0; 32];
-      file.read_exact(&mut iv).unwrap();
-      file.read_exact(&mut key).unwrap();
-      (iv, key)
+      read_exact(file, &mut iv)?;
+      read_exact(file, &mut key)?;
+      Ok((iv, key))
     }
 
-    fn hmac_sha256(file: &mut File) -> [u8; 32] {
+    fn hmac_sha256<R: Read>(file: &mut R) -> Result<[u8; 32]> {
       let mut hmac = [0; 32];
-      file.read_exact(&mut hmac).unwrap();
-      hmac
+      read_exact(file, &mut hmac)?;
+      Ok(hmac)
+    }
+
+    #[test]
+    fn missing_file() {
+      match get_file("tests/fixtures/does_not_exist.aes") {
+        Err(Error::Io(ref e)) => assert_eq!(e.kind(), io::ErrorKind::NotFound),
+        _ => panic!("expected an io error"),
+      }
+    }
+
+    #[test]
+    fn not_aes_crypt() {
+      let dir = Scratch::new("detect-not-aes-crypt");
+
+      for bytes in &[&b""[..], b"plain text\n", b"\xff\xfe\x00"] {
+        let path = dir.write("plain", bytes);
+        assert!(matches!(get_file(&path), Err(Error::NotAesCrypt)));
+      }
+    }
+
+    #[test]
+    fn unsupported_version() {
+      let dir = Scratch::new("detect-unsupported-version");
+      let path = dir.write("version", b"AES\x07\x00");
+      assert!(matches!(get_file(&path), Err(Error::UnsupportedVersion(7))));
+    }
+
+    #[test]
+    fn truncated_header() {
+      let dir = Scratch::new("detect-truncated-header");
+
+      let mut bytes = fixture("hello_v2.txt.aes");
+      bytes.truncate(20);
+
+      let path = dir.write("truncated", &bytes);
+      assert!(matches!(get_file(&path), Err(Error::Truncated { .. })));
+
+      let path = dir.write("short", b"AE");
+      assert!(matches!(get_file(&path), Err(Error::Truncated { expected: 3, actual: 2 })));
+    }
+
+    #[test]
+    fn malformed_extension() {
+      let dir = Scratch::new("detect-malformed-extension");
+
+      // identifier with no 0x00 terminator
+      let path = dir.write("malformed", b"AES\x02\x00\x00\x03abc\x00\x00");
+      assert!(matches!(get_file(&path), Err(Error::MalformedExtension)));
+    }
+
+    #[test]
+    fn detect_from_bytes() {
+      for version in 0..4 {
+        let path = format!("tests/fixtures/hello_v{}.txt.aes", version);
+        let bytes = std::fs::read(&path).unwrap();
+
+        let detected = from_bytes(&bytes).unwrap();
+        let file = get_file(&path).unwrap();
+
+        assert_eq!(detected.version(), version);
+        assert_eq!(detected.path(), None);
+        assert_eq!(detected.extensions(), file.extensions());
+        assert_eq!(file.path(), Some(path.as_str()));
+      }
+
+      let bytes = fixture("hello_v3.txt.aes");
+      assert_eq!(from_bytes(&bytes).unwrap().kdf_iterations(), Some(1000));
+      assert_eq!(get_file("tests/fixtures/hello_v2.txt.aes").unwrap().kdf_iterations(), None);
+    }
+
+    #[test]
+    fn detect_from_reader_position() {
+      let mut bytes = fixture("hello_v2.txt.aes");
+      let len = bytes.len();
+      bytes.splice(0..0, b"archive member header".iter().cloned());
+
+      let mut cursor = io::Cursor::new(bytes);
+      cursor.seek(io::SeekFrom::Start(21)).unwrap();
+
+      let file = from_reader(&mut cursor).unwrap();
+
+      assert_eq!(file.version(), 2);
+      // left just past the extension terminator
+      assert_eq!(cursor.position() as usize, 21 + len - (16 + 48 + 32 + 32 + 1 + 32));
+      assert!(file.decrypt("password").is_err());
     }
 }
@@ -0,0 +1,562 @@
+use std::collections::HashSet;
+use std::fs::{self, File, ReadDir};
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{sync_channel, Receiver};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use glob::Pattern;
+
+use crate::{detect, AesFile};
+use crate::error::{Error, Result};
+use crate::util::Counted;
+#[cfg(test)]
+use crate::testing::Scratch;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SymlinkPolicy {
+    // symbolic links are not reported or descended into
+    Skip,
+    // links are resolved; directories already visited are not entered again
+    Follow,
+}
+
+// Settings for `scan_dir`. Glob patterns are matched against paths relative
+// to the scanned root; `exclude` also prunes directories, `include` only
+// applies to files and, when empty, lets every file through.
+
+#[derive(Clone, Debug)]
+pub struct ScanOptions {
+    symlinks: SymlinkPolicy,
+    max_depth: Option<usize>,
+    include: Vec<String>,
+    exclude: Vec<String>,
+    threads: usize,
+    max_open_files: Option<usize>,
+}
+
+impl ScanOptions {
+    pub fn new() -> Self {
+        ScanOptions {
+            symlinks: SymlinkPolicy::Skip,
+            max_depth: None,
+            include: vec![],
+            exclude: vec![],
+            threads: 0,
+            max_open_files: None,
+        }
+    }
+
+    pub fn symlinks(mut self, policy: SymlinkPolicy) -> Self {
+        self.symlinks = policy;
+        self
+    }
+
+    // entries directly inside the root are at depth 1
+    pub fn max_depth(mut self, depth: usize) -> Self {
+        self.max_depth = Some(depth);
+        self
+    }
+
+    pub fn include(mut self, pattern: &str) -> Self {
+        self.include.push(pattern.to_string());
+        self
+    }
+
+    pub fn exclude(mut self, pattern: &str) -> Self {
+        self.exclude.push(pattern.to_string());
+        self
+    }
+
+    // worker threads for `scan_dir_parallel`; defaults to the number of CPUs
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.threads = threads;
+        self
+    }
+
+    // files the workers may hold open at once; defaults to the thread count
+    pub fn max_open_files(mut self, limit: usize) -> Self {
+        self.max_open_files = Some(limit);
+        self
+    }
+
+    fn thread_count(&self) -> usize {
+        match self.threads {
+            0 => thread::available_parallelism().map(|n| n.get()).unwrap_or(4),
+            n => n,
+        }
+    }
+}
+
+impl Default for ScanOptions {
+    fn default() -> Self { Self::new() }
+}
+
+// One visited file, or a directory that could not be read. Files that are
+// not AES Crypt carry `Err(Error::NotAesCrypt)`.
+
+#[derive(Debug)]
+pub struct ScanEntry {
+    pub path: PathBuf,
+    pub depth: usize,
+    pub result: Result<AesFile>,
+}
+
+impl ScanEntry {
+    pub fn version(&self) -> Option<u8> {
+        self.result.as_ref().ok().map(|f| f.version)
+    }
+
+    pub fn is_aes_crypt(&self) -> bool {
+        self.result.is_ok()
+    }
+}
+
+// Walks `root` depth first, yielding an entry for every file that passes
+// the filters. Errors are reported per entry and never end the walk.
+pub fn scan_dir<P: AsRef<Path>>(root: P, options: &ScanOptions) -> Result<ScanDir> {
+    Ok(ScanDir {
+        root: root.as_ref().to_path_buf(),
+        pending: Some(root.as_ref().to_path_buf()),
+        stack: vec![],
+        visited: HashSet::new(),
+        symlinks: options.symlinks,
+        max_depth: options.max_depth,
+        include: compile(&options.include)?,
+        exclude: compile(&options.exclude)?,
+    })
+}
+
+fn compile(patterns: &[String]) -> Result<Vec<Pattern>> {
+    patterns.iter()
+        .map(|p| Pattern::new(p).map_err(|_| Error::InvalidInput("invalid glob pattern")))
+        .collect()
+}
+
+// enough for the header and a typical extension block in one read
+const HEADER_PROBE_SIZE: usize = 512;
+
+enum Visit {
+    File(PathBuf, usize),
+    Failed(ScanEntry),
+}
+
+pub struct ScanDir {
+    root: PathBuf,
+    // the root itself, until it has been looked at
+    pending: Option<PathBuf>,
+    // open directories and the depth of their entries
+    stack: Vec<(PathBuf, ReadDir, usize)>,
+    // canonical directories entered while following symlinks
+    visited: HashSet<PathBuf>,
+    symlinks: SymlinkPolicy,
+    max_depth: Option<usize>,
+    include: Vec<Pattern>,
+    exclude: Vec<Pattern>,
+}
+
+impl ScanDir {
+    fn matches(patterns: &[Pattern], relative: &Path) -> bool {
+        patterns.iter().any(|p| p.matches_path(relative))
+    }
+
+    // Opens a directory for walking; returns an entry when it can't be read.
+    fn enter(&mut self, path: PathBuf, depth: usize) -> Option<ScanEntry> {
+        if self.symlinks == SymlinkPolicy::Follow {
+            match fs::canonicalize(&path) {
+                Ok(canonical) => if !self.visited.insert(canonical) { return None },
+                Err(e) => return Some(ScanEntry { path, depth, result: Err(e.into()) }),
+            }
+        }
+
+        match fs::read_dir(&path) {
+            Ok(entries) => {
+                self.stack.push((path, entries, depth + 1));
+                None
+            },
+            Err(e) => Some(ScanEntry { path, depth, result: Err(e.into()) }),
+        }
+    }
+
+    fn visit_root(&mut self, path: PathBuf) -> Option<Visit> {
+        match fs::metadata(&path) {
+            Ok(ref meta) if meta.is_dir() => self.enter(path, 0).map(Visit::Failed),
+            Ok(_) => Some(Visit::File(path, 0)),
+            Err(e) => Some(Visit::Failed(ScanEntry { path, depth: 0, result: Err(e.into()) })),
+        }
+    }
+
+    // the next file to inspect, or a failure to report as it is
+    fn walk(&mut self) -> Option<Visit> {
+        if let Some(root) = self.pending.take() {
+            if let Some(visit) = self.visit_root(root) { return Some(visit) }
+        }
+
+        loop {
+            let (entry, depth) = {
+                let (dir, entries, depth) = self.stack.last_mut()?;
+
+                match entries.next() {
+                    None => {
+                        self.stack.pop();
+                        continue
+                    },
+                    Some(Err(e)) => return Some(Visit::Failed(ScanEntry { path: dir.clone(), depth: *depth - 1, result: Err(e.into()) })),
+                    Some(Ok(entry)) => (entry, *depth),
+                }
+            };
+
+            let path = entry.path();
+            let relative = path.strip_prefix(&self.root).unwrap_or(&path).to_path_buf();
+
+            if Self::matches(&self.exclude, &relative) { continue }
+
+            let file_type = match entry.file_type() {
+                Ok(file_type) => file_type,
+                Err(e) => return Some(Visit::Failed(ScanEntry { path, depth, result: Err(e.into()) })),
+            };
+
+            let (is_dir, is_file) = if file_type.is_symlink() {
+                if self.symlinks == SymlinkPolicy::Skip { continue }
+
+                match fs::metadata(&path) {
+                    Ok(meta) => (meta.is_dir(), meta.is_file()),
+                    // dangling link
+                    Err(e) => return Some(Visit::Failed(ScanEntry { path, depth, result: Err(e.into()) })),
+                }
+            } else {
+                (file_type.is_dir(), file_type.is_file())
+            };
+
+            if is_dir {
+                if self.max_depth.is_none_or(|max| depth < max) {
+                    if let Some(entry) = self.enter(path, depth) { return Some(Visit::Failed(entry)) }
+                }
+                continue
+            }
+
+            if !is_file { continue }
+
+            if !self.include.is_empty() && !Self::matches(&self.include, &relative) { continue }
+
+            return Some(Visit::File(path, depth))
+        }
+    }
+}
+
+impl Iterator for ScanDir {
+    type Item = ScanEntry;
+
+    fn next(&mut self) -> Option<ScanEntry> {
+        match self.walk()? {
+            Visit::File(path, depth) => Some(inspect(path, depth).0),
+            Visit::Failed(entry) => Some(entry),
+        }
+    }
+}
+
+fn inspect(path: PathBuf, depth: usize) -> (ScanEntry, u64) {
+    let mut read = 0;
+
+    let result = match path.to_str() {
+        Some(file) => probe(file, &mut read),
+        None => Err(Error::InvalidInput("path is not valid UTF-8")),
+    };
+
+    (ScanEntry { path, depth, result }, read)
+}
+
+// `detect::get_file` with a small read buffer, so a scan reads little
+// beyond the header; `read` counts the octets taken from the file.
+fn probe(file: &str, read: &mut u64) -> Result<AesFile> {
+    let counted = Counted { inner: File::open(file)?, count: read };
+
+    let mut aes_file = detect::from_reader(BufReader::with_capacity(HEADER_PROBE_SIZE, counted))?;
+    aes_file.file = Some(file.to_string());
+
+    Ok(aes_file)
+}
+
+// Aggregate figures for a parallel scan. Taken while the scan is running
+// they cover the entries produced so far.
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ScanStats {
+    pub files: u64,
+    pub aes_files: u64,
+    // entries failing with anything but `NotAesCrypt`, unreadable
+    // directories included
+    pub failed: u64,
+    pub bytes_read: u64,
+    pub elapsed: Duration,
+}
+
+impl ScanStats {
+    pub fn files_per_second(&self) -> f64 {
+        per_second(self.files, self.elapsed)
+    }
+
+    pub fn bytes_per_second(&self) -> f64 {
+        per_second(self.bytes_read, self.elapsed)
+    }
+}
+
+fn per_second(count: u64, elapsed: Duration) -> f64 {
+    match elapsed.as_secs_f64() {
+        secs if secs > 0.0 => count as f64 / secs,
+        _ => 0.0,
+    }
+}
+
+#[derive(Default)]
+struct Counters {
+    files: AtomicU64,
+    aes_files: AtomicU64,
+    failed: AtomicU64,
+    bytes_read: AtomicU64,
+}
+
+impl Counters {
+    fn record(&self, entry: &ScanEntry, read: Option<u64>) {
+        if let Some(read) = read {
+            self.files.fetch_add(1, Ordering::Relaxed);
+            self.bytes_read.fetch_add(read, Ordering::Relaxed);
+        }
+
+        match entry.result {
+            Ok(_) => self.aes_files.fetch_add(1, Ordering::Relaxed),
+            Err(Error::NotAesCrypt) => 0,
+            Err(_) => self.failed.fetch_add(1, Ordering::Relaxed),
+        };
+    }
+}
+
+// counting semaphore limiting the files open across workers
+struct Permits {
+    available: Mutex<usize>,
+    released: Condvar,
+}
+
+impl Permits {
+    fn acquire(&self) {
+        let mut available = self.available.lock().unwrap();
+        while *available == 0 {
+            available = self.released.wait(available).unwrap();
+        }
+        *available -= 1;
+    }
+
+    fn release(&self) {
+        *self.available.lock().unwrap() += 1;
+        self.released.notify_one();
+    }
+}
+
+// Entries from `scan_dir_parallel`, in the order the workers finish them.
+// Dropping it before the end stops the walk and the workers.
+
+pub struct ParallelScan {
+    results: Receiver<ScanEntry>,
+    counters: Arc<Counters>,
+    started: Instant,
+    finished: Option<Duration>,
+}
+
+impl ParallelScan {
+    pub fn stats(&self) -> ScanStats {
+        ScanStats {
+            files: self.counters.files.load(Ordering::Relaxed),
+            aes_files: self.counters.aes_files.load(Ordering::Relaxed),
+            failed: self.counters.failed.load(Ordering::Relaxed),
+            bytes_read: self.counters.bytes_read.load(Ordering::Relaxed),
+            elapsed: self.finished.unwrap_or_else(|| self.started.elapsed()),
+        }
+    }
+}
+
+impl Iterator for ParallelScan {
+    type Item = ScanEntry;
+
+    fn next(&mut self) -> Option<ScanEntry> {
+        match self.results.recv() {
+            Ok(entry) => Some(entry),
+            Err(_) => {
+                if self.finished.is_none() { self.finished = Some(self.started.elapsed()) }
+                None
+            },
+        }
+    }
+}
+
+// `scan_dir` with the directory walk on one thread and the header reads
+// spread over `options.threads` workers.
+pub fn scan_dir_parallel<P: AsRef<Path>>(root: P, options: &ScanOptions) -> Result<ParallelScan> {
+    let mut walk = scan_dir(root, options)?;
+
+    let threads = options.thread_count();
+    let permits = Arc::new(Permits {
+        available: Mutex::new(options.max_open_files.unwrap_or(threads).max(1)),
+        released: Condvar::new(),
+    });
+    let counters = Arc::new(Counters::default());
+
+    let (paths_tx, paths_rx) = sync_channel::<(PathBuf, usize)>(threads * 4);
+    let (results_tx, results_rx) = sync_channel(threads * 4);
+    let paths_rx = Arc::new(Mutex::new(paths_rx));
+
+    for _ in 0..threads {
+        let paths = paths_rx.clone();
+        let results = results_tx.clone();
+        let permits = permits.clone();
+        let counters = counters.clone();
+
+        thread::spawn(move || loop {
+            // the lock is let go before the file is opened
+            let next = paths.lock().unwrap().recv();
+            let (path, depth) = match next {
+                Ok(next) => next,
+                Err(_) => return,
+            };
+
+            permits.acquire();
+            let (entry, read) = inspect(path, depth);
+            permits.release();
+
+            counters.record(&entry, Some(read));
+            if results.send(entry).is_err() { return }
+        });
+    }
+
+    let walk_counters = counters.clone();
+
+    thread::spawn(move || {
+        while let Some(visit) = walk.walk() {
+            let sent = match visit {
+                Visit::File(path, depth) => paths_tx.send((path, depth)).is_ok(),
+                Visit::Failed(entry) => {
+                    walk_counters.record(&entry, None);
+                    results_tx.send(entry).is_ok()
+                },
+            };
+
+            if !sent { return }
+        }
+    });
+
+    Ok(ParallelScan { results: results_rx, counters, started: Instant::now(), finished: None })
+}
+
+#[cfg(test)]
+fn scratch_tree(name: &str) -> Scratch {
+    let root = Scratch::new(&format!("scan-{}", name));
+
+    root.copy_of("hello_v0.txt.aes", "v0.aes");
+    root.copy_of("hello_v2.txt.aes", "a/v2.aes");
+    root.copy_of("hello_v3.txt.aes", "a/b/c/v3.aes");
+    root.copy_of("hello_v1.txt.aes", "skip/v1.aes");
+    root.write("a/b/notes.txt", b"plain text");
+
+    root
+}
+
+#[cfg(test)]
+fn found(scan: ScanDir, root: &Path) -> Vec<(String, Option<u8>)> {
+    let mut found: Vec<_> = scan
+        .map(|e| (e.path.strip_prefix(root).unwrap().to_str().unwrap().to_string(), e.version()))
+        .collect();
+    found.sort();
+    found
+}
+
+#[test]
+fn scan_whole_tree() {
+    let root = scratch_tree("whole");
+    let scan = scan_dir(&root, &ScanOptions::new()).unwrap();
+
+    assert_eq!(found(scan, &root), vec![
+        ("a/b/c/v3.aes".to_string(), Some(3)),
+        ("a/b/notes.txt".to_string(), None),
+        ("a/v2.aes".to_string(), Some(2)),
+        ("skip/v1.aes".to_string(), Some(1)),
+        ("v0.aes".to_string(), Some(0)),
+    ]);
+}
+
+#[test]
+fn scan_filters() {
+    let root = scratch_tree("filters");
+
+    let options = ScanOptions::new().max_depth(2).include("*.aes").exclude("skip");
+    let scan = scan_dir(&root, &options).unwrap();
+
+    assert_eq!(found(scan, &root), vec![
+        ("a/v2.aes".to_string(), Some(2)),
+        ("v0.aes".to_string(), Some(0)),
+    ]);
+
+    assert!(scan_dir(&root, &ScanOptions::new().include("[")).is_err());
+}
+
+#[cfg(unix)]
+#[test]
+fn scan_symlinks() {
+    let root = scratch_tree("symlinks");
+
+    // a loop back to the root and a dangling link
+    std::os::unix::fs::symlink(&root, root.join("a/b/loop")).unwrap();
+    std::os::unix::fs::symlink(root.join("missing"), root.join("dangling")).unwrap();
+
+    let skipped = scan_dir(&root, &ScanOptions::new()).unwrap().count();
+    assert_eq!(skipped, 5);
+
+    let followed: Vec<_> = scan_dir(&root, &ScanOptions::new().symlinks(SymlinkPolicy::Follow)).unwrap().collect();
+    assert_eq!(followed.len(), 6);
+
+    let dangling = followed.iter().find(|e| e.path.ends_with("dangling")).unwrap();
+    assert!(matches!(dangling.result, Err(Error::Io(_))));
+}
+
+#[test]
+fn scan_missing_root() {
+    let entries: Vec<_> = scan_dir("tests/fixtures/no_such_dir", &ScanOptions::new()).unwrap().collect();
+
+    assert_eq!(entries.len(), 1);
+    assert!(matches!(entries[0].result, Err(Error::Io(_))));
+}
+
+#[test]
+fn parallel_matches_sequential() {
+    let root = scratch_tree("parallel");
+
+    let options = ScanOptions::new().threads(4).max_open_files(2);
+    let mut scan = scan_dir_parallel(&root, &options).unwrap();
+    let mut entries: Vec<_> = scan.by_ref().map(|e| (e.version(), e.path)).collect();
+    entries.sort();
+
+    let mut sequential: Vec<_> = scan_dir(&root, &options).unwrap().map(|e| (e.version(), e.path)).collect();
+    sequential.sort();
+
+    assert_eq!(entries, sequential);
+
+    let stats = scan.stats();
+    assert_eq!((stats.files, stats.aes_files, stats.failed), (5, 4, 0));
+    assert_eq!(stats, scan.stats());
+}
+
+#[test]
+fn parallel_reads_only_headers() {
+    let root = Scratch::new("scan-large");
+    root.write("large.bin", &vec![0x41u8; 1 << 20]);
+    root.copy_of("hello_v2.txt.aes", "v2.aes");
+
+    let mut scan = scan_dir_parallel(&root, &ScanOptions::new().threads(2)).unwrap();
+    assert_eq!(scan.by_ref().count(), 2);
+
+    // one buffered read of each file
+    let stats = scan.stats();
+    assert_eq!(stats.bytes_read, 512 + 324);
+    assert!(stats.files_per_second() > 0.0);
+}
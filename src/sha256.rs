@@ -1,6 +1,5 @@
 // FIPS 180-2 compliant
-use core::array::FixedSizeArray; // for `as_slice`
-use std::{slice, ptr, str};
+use std::str;
 
 use crate::algorithms::*;
 use crate::util::{memset, SliceToHex};
@@ -10,6 +9,8 @@ pub(crate) struct SHA256Context {
     pub(crate) total: [u32; 2],
     pub(crate) state: [u32; 8], // H
     pub(crate) buffer: [u8; 64],
+    pub(crate) ipad: [u8; 64],
+    pub(crate) opad: [u8; 64],
 }
 
 impl SHA256Context {
@@ -32,7 +33,13 @@ pub(crate) fn starts(context: Option<&mut SHA256Context>) -> SHA256Context {
 
     match context {
         Some(ctx) => { ctx.total = [0u32; 2]; ctx.state = state; ctx.clone() },
-        None => SHA256Context { total: [0u32; 2], state: state, buffer: [0u8; 64] },
+        None => SHA256Context {
+            total: [0u32; 2],
+            state,
+            buffer: [0u8; 64],
+            ipad: [0u8; 64],
+            opad: [0u8; 64],
+        },
     }
 }
 
@@ -144,65 +151,55 @@ pub(crate) fn process(state: &mut [u32], data: &[u8]) {
 
 pub(crate) fn update(context: &mut SHA256Context, input: &[u8], length: &mut u32) {
     if *length == 0 { return; }
-    let mut left = context.total[0] & 0x3F;
+    let mut left = (context.total[0] & 0x3F) as usize;
     let fill = 64 - left;
 
-    context.total[0] += *length;
-    context.total[0] &= 0xFFFFFFFF;
+    context.total[0] = context.total[0].wrapping_add(*length);
 
     if context.total[0] < *length {
         context.total[1] += 1;
     }
 
-    let bfr_ptr = context.buffer.as_ptr() as *mut u8;
-    let mut ipt_ptr = input.as_ptr() as *mut u8;
+    let mut input = &input[..*length as usize];
 
-    if left != 0 && *length >= fill {
-        unsafe { ptr::copy_nonoverlapping(ipt_ptr, bfr_ptr.add(left as usize), fill as usize); }
+    if left != 0 && input.len() >= fill {
+        context.buffer[left..].copy_from_slice(&input[..fill]);
 
         process(&mut context.state, &context.buffer);
 
-        *length -= fill;
-        ipt_ptr = unsafe { ipt_ptr.add(fill as usize) };
+        input = &input[fill..];
         left = 0;
     }
 
-    while *length >= 64 {
-        let temp_input: &[u8] = unsafe { slice::from_raw_parts(ipt_ptr, 64) };
-        process(&mut context.state, temp_input);
-        *length -= 64;
-        ipt_ptr = unsafe { ipt_ptr.add(64) };
+    while input.len() >= 64 {
+        process(&mut context.state, &input[..64]);
+        input = &input[64..];
     }
 
-    if *length != 0 {
-        let temp_input: &[u8] = unsafe { slice::from_raw_parts(ipt_ptr, 64) };
-        unsafe { ptr::copy_nonoverlapping(ipt_ptr, bfr_ptr.add(left as usize), fill as usize); }
-    }
+    context.buffer[left..left + input.len()].copy_from_slice(input);
+    *length = input.len() as u32;
 }
 
 pub(crate) fn finish(context: &mut SHA256Context, digest: &mut [u8; 32]) {
-    let mut last: u32 = context.total[0] & 0x3F;
+    let mut last = (context.total[0] & 0x3F) as usize;
 
-    context.buffer[last as usize] = 0x80;
+    context.buffer[last] = 0x80;
     last += 1;
 
-    let bfr_ptr = context.buffer.as_ptr() as *mut u8;
-
-    if last < 56 {
+    if last <= 56 {
         // Enough room for padding + length in current block
-        memset(unsafe { bfr_ptr.add(last as usize) }, 0, 56 - last as usize);
+        memset(context.buffer[last..].as_mut_ptr(), 0, 56 - last);
     } else {
         // We'll need an extra block.
-        memset(unsafe { bfr_ptr.add(last as usize) }, 0, 64 - last as usize);
+        memset(context.buffer[last..].as_mut_ptr(), 0, 64 - last);
 
         process(&mut context.state, &context.buffer);
 
-        memset(bfr_ptr, 0, 56);
+        memset(context.buffer.as_mut_ptr(), 0, 56);
     };
 
     let high: u32 = (context.total[0] >> 29) | (context.total[1] <<  3);
     let low:  u32 =  context.total[0] <<  3;
-    let msglen: &mut [u8] = &mut [0u8; 8];
 
     put_u32(high, &mut context.buffer, 56);
     put_u32(low , &mut context.buffer, 60);
@@ -219,6 +216,121 @@ pub(crate) fn finish(context: &mut SHA256Context, digest: &mut [u8; 32]) {
     put_u32(context.state[7], digest, 28);
 }
 
+// Streaming SHA-256 over the functions above.
+//
+//     let mut hasher = Sha256::new();
+//     hasher.update(b"Hello, ");
+//     hasher.update(b"AES Crypt!");
+//     let digest: [u8; 32] = hasher.finalize();
+
+#[derive(Clone)]
+pub struct Sha256 {
+    context: SHA256Context,
+}
+
+impl Sha256 {
+    pub fn new() -> Self {
+        Sha256 { context: starts(None) }
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        // `update` counts in u32, so feed anything longer in pieces
+        for piece in data.chunks(u32::MAX as usize) {
+            update(&mut self.context, piece, &mut (piece.len() as u32));
+        }
+    }
+
+    pub fn finalize(mut self) -> [u8; 32] {
+        let mut digest = [0u8; 32];
+        finish(&mut self.context, &mut digest);
+        digest
+    }
+
+    // Lowercase hex of the final digest.
+    pub fn hex_digest(self) -> String {
+        <[u8]>::slice_to_hex(&self.finalize())
+    }
+
+    pub fn digest(data: &[u8]) -> [u8; 32] {
+        let mut hasher = Sha256::new();
+        hasher.update(data);
+        hasher.finalize()
+    }
+}
+
+impl Default for Sha256 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// SHA-256 HMAC context setup
+
+pub(crate) fn hmac_starts(key: &[u8]) -> SHA256Context {
+    let mut sum = [0u8; 32];
+
+    let key = if key.len() > 64 {
+        let mut ctx = starts(None);
+        update(&mut ctx, key, &mut (key.len() as u32));
+        finish(&mut ctx, &mut sum);
+        &sum[..]
+    } else {
+        key
+    };
+
+    let mut ctx = starts(None);
+
+    memset(ctx.ipad.as_mut_ptr(), 0x36, 64);
+    memset(ctx.opad.as_mut_ptr(), 0x5C, 64);
+
+    for i in 0..key.len() {
+        ctx.ipad[i] ^= key[i];
+        ctx.opad[i] ^= key[i];
+    }
+
+    let ipad = ctx.ipad;
+    update(&mut ctx, &ipad, &mut 64);
+
+    ctx
+}
+
+// SHA-256 HMAC process buffer
+
+pub(crate) fn hmac_update(context: &mut SHA256Context, input: &[u8]) {
+    // `update` counts in u32, so feed anything longer in pieces
+    for piece in input.chunks(u32::MAX as usize) {
+        update(context, piece, &mut (piece.len() as u32));
+    }
+}
+
+// SHA-256 HMAC final digest
+
+pub(crate) fn hmac_finish(context: &mut SHA256Context, digest: &mut [u8; 32]) {
+    let mut tmpbuf = [0u8; 32];
+
+    finish(context, &mut tmpbuf);
+
+    starts(Some(context));
+
+    let opad = context.opad;
+    update(context, &opad, &mut 64);
+    update(context, &tmpbuf, &mut 32);
+
+    finish(context, digest);
+}
+
+// output = HMAC-SHA-256( key, input )
+
+pub(crate) fn hmac(key: &[u8], input: &[u8]) -> [u8; 32] {
+    let mut digest = [0u8; 32];
+    let mut ctx = hmac_starts(key);
+
+    hmac_update(&mut ctx, input);
+    hmac_finish(&mut ctx, &mut digest);
+
+    digest
+}
+
 #[test]
 fn one_block_message() {
     let msg: &'static str = "abc";
@@ -275,3 +387,151 @@ fn long_message() {
 
     assert_eq!( ctx.hex_digest(), val );
 }
+
+#[test]
+fn hmac_rfc4231_case_2() {
+    let digest = hmac(b"Jefe", b"what do ya want for nothing?");
+
+    assert_eq!(
+        <[u8]>::slice_to_hex(&digest),
+        "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+    );
+}
+
+#[test]
+fn hmac_rfc4231_case_6() {
+    let key = [0xAAu8; 131];
+    let digest = hmac(&key, b"Test Using Larger Than Block-Size Key - Hash Key First");
+
+    assert_eq!(
+        <[u8]>::slice_to_hex(&digest),
+        "60e431591ee0b67f0d8a26aacbf5b77f8e0bc6213728c5140546040f0ee37f54"
+    );
+}
+
+// NIST SHAVS, byte oriented. Each message is hashed in one `update` and
+// again in pieces of random size, empty ones included, so that the
+// buffer carry in `update` gets exercised at every offset.
+
+#[cfg(test)]
+fn digest_of(message: &[u8]) -> [u8; 32] {
+    let mut ctx = starts(None);
+    let mut digest = [0u8; 32];
+
+    update(&mut ctx, message, &mut (message.len() as u32));
+    finish(&mut ctx, &mut digest);
+
+    digest
+}
+
+#[cfg(test)]
+fn chunked_digest_of(message: &[u8], rng: &mut rand::rngs::StdRng) -> [u8; 32] {
+    use rand::Rng;
+
+    let mut ctx = starts(None);
+    let mut digest = [0u8; 32];
+    let mut rest = message;
+
+    while !rest.is_empty() {
+        let n = rng.gen_range(0..=rest.len().min(150));
+        let (piece, tail) = rest.split_at(n);
+
+        update(&mut ctx, piece, &mut (n as u32));
+        rest = tail;
+    }
+
+    finish(&mut ctx, &mut digest);
+
+    digest
+}
+
+#[cfg(test)]
+fn shavs_messages(path: &str) {
+    use rand::SeedableRng;
+    use crate::cavp;
+
+    let sections = cavp::load(path);
+    assert_eq!(sections.len(), 1);
+    assert_eq!(sections[0].header, "L = 32");
+
+    let mut rng = rand::rngs::StdRng::seed_from_u64(180);
+
+    for record in &sections[0].records {
+        let length = record.number("Len") / 8;
+        let message = &record.bytes("Msg")[..length];
+        let expected = record.bytes("MD");
+
+        assert_eq!(digest_of(message).to_vec(), expected, "{} Len = {}", path, record.get("Len"));
+
+        for _ in 0..8 {
+            assert_eq!(chunked_digest_of(message, &mut rng).to_vec(), expected, "{} Len = {} in pieces", path, record.get("Len"));
+        }
+    }
+}
+
+#[test]
+fn shavs_short_msg() {
+    shavs_messages("tests/cavp/sha/SHA256ShortMsg.rsp");
+}
+
+#[test]
+fn shavs_long_msg() {
+    shavs_messages("tests/cavp/sha/SHA256LongMsg.rsp");
+}
+
+// SHAVS pseudorandom message test: each digest of the last three becomes
+// the next, and every 1000th one is checked.
+#[test]
+fn shavs_monte() {
+    use crate::cavp;
+
+    let path = "tests/cavp/sha/SHA256Monte.rsp";
+    let sections = cavp::load(path);
+
+    let mut seed = sections[0].records[0].bytes("Seed");
+
+    for record in &sections[0].records[1..] {
+        let mut md = [seed.clone(), seed.clone(), seed.clone()];
+
+        for _ in 0..1000 {
+            let mut ctx = starts(None);
+            let mut digest = [0u8; 32];
+
+            for m in &md {
+                update(&mut ctx, m, &mut (m.len() as u32));
+            }
+            finish(&mut ctx, &mut digest);
+
+            md = [md[1].clone(), md[2].clone(), digest.to_vec()];
+        }
+
+        seed = md[2].clone();
+        assert_eq!(seed, record.bytes("MD"), "{} COUNT = {}", path, record.get("COUNT"));
+    }
+}
+
+#[test]
+fn sha256_hasher() {
+    let msg = b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq";
+    let val = "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1";
+
+    assert_eq!(<[u8]>::slice_to_hex(&Sha256::digest(msg)), val);
+
+    let mut hasher = Sha256::new();
+    for piece in msg.chunks(7) {
+        hasher.update(piece);
+    }
+    hasher.update(b"");
+
+    // a clone carries on independently
+    let mut longer = hasher.clone();
+    longer.update(b"!");
+
+    assert_eq!(hasher.hex_digest(), val);
+    assert_eq!(longer.finalize(), digest_of(b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq!"));
+
+    assert_eq!(
+        Sha256::default().hex_digest(),
+        "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+    );
+}
@@ -0,0 +1,271 @@
+// FIPS 180-2 compliant
+use crate::algorithms::{get_u64, put_u64};
+use crate::util::{memset, SliceToHex};
+
+#[derive(Clone)]
+pub(crate) struct SHA512Context {
+    pub(crate) total: [u64; 2],
+    pub(crate) state: [u64; 8], // H
+    pub(crate) buffer: [u8; 128],
+    pub(crate) ipad: [u8; 128],
+    pub(crate) opad: [u8; 128],
+}
+
+const K: [u64; 80] = [
+    0x428A2F98D728AE22, 0x7137449123EF65CD, 0xB5C0FBCFEC4D3B2F, 0xE9B5DBA58189DBBC,
+    0x3956C25BF348B538, 0x59F111F1B605D019, 0x923F82A4AF194F9B, 0xAB1C5ED5DA6D8118,
+    0xD807AA98A3030242, 0x12835B0145706FBE, 0x243185BE4EE4B28C, 0x550C7DC3D5FFB4E2,
+    0x72BE5D74F27B896F, 0x80DEB1FE3B1696B1, 0x9BDC06A725C71235, 0xC19BF174CF692694,
+    0xE49B69C19EF14AD2, 0xEFBE4786384F25E3, 0x0FC19DC68B8CD5B5, 0x240CA1CC77AC9C65,
+    0x2DE92C6F592B0275, 0x4A7484AA6EA6E483, 0x5CB0A9DCBD41FBD4, 0x76F988DA831153B5,
+    0x983E5152EE66DFAB, 0xA831C66D2DB43210, 0xB00327C898FB213F, 0xBF597FC7BEEF0EE4,
+    0xC6E00BF33DA88FC2, 0xD5A79147930AA725, 0x06CA6351E003826F, 0x142929670A0E6E70,
+    0x27B70A8546D22FFC, 0x2E1B21385C26C926, 0x4D2C6DFC5AC42AED, 0x53380D139D95B3DF,
+    0x650A73548BAF63DE, 0x766A0ABB3C77B2A8, 0x81C2C92E47EDAEE6, 0x92722C851482353B,
+    0xA2BFE8A14CF10364, 0xA81A664BBC423001, 0xC24B8B70D0F89791, 0xC76C51A30654BE30,
+    0xD192E819D6EF5218, 0xD69906245565A910, 0xF40E35855771202A, 0x106AA07032BBD1B8,
+    0x19A4C116B8D2D0C8, 0x1E376C085141AB53, 0x2748774CDF8EEB99, 0x34B0BCB5E19B48A8,
+    0x391C0CB3C5C95A63, 0x4ED8AA4AE3418ACB, 0x5B9CCA4F7763E373, 0x682E6FF3D6B2B8A3,
+    0x748F82EE5DEFB2FC, 0x78A5636F43172F60, 0x84C87814A1F0AB72, 0x8CC702081A6439EC,
+    0x90BEFFFA23631E28, 0xA4506CEBDE82BDE9, 0xBEF9A3F7B2C67915, 0xC67178F2E372532B,
+    0xCA273ECEEA26619C, 0xD186B8C721C0C207, 0xEADA7DD6CDE0EB1E, 0xF57D4F7FEE6ED178,
+    0x06F067AA72176FBA, 0x0A637DC5A2C898A6, 0x113F9804BEF90DAE, 0x1B710B35131C471B,
+    0x28DB77F523047D84, 0x32CAAB7B40C72493, 0x3C9EBE0A15C9BEBC, 0x431D67C49C100D4C,
+    0x4CC5D4BECB3E42B6, 0x597F299CFC657E2A, 0x5FCB6FAB3AD6FAEC, 0x6C44198C4A475817,
+];
+
+// σ{512}0(x) = ROTR¹(x) ⊕ ROTR⁸(x) ⊕ SHR⁷(x)
+#[inline(always)] fn s0(x: u64) -> u64 { x.rotate_right( 1) ^ x.rotate_right( 8) ^ (x >> 7) }
+// σ{512}1(x) = ROTR¹⁹(x) ⊕ ROTR⁶¹(x) ⊕ SHR⁶(x)
+#[inline(always)] fn s1(x: u64) -> u64 { x.rotate_right(19) ^ x.rotate_right(61) ^ (x >> 6) }
+// Σ{512}0(x) = ROTR²⁸(x) ⊕ ROTR³⁴(x) ⊕ ROTR³⁹(x)
+#[inline(always)] fn s2(x: u64) -> u64 { x.rotate_right(28) ^ x.rotate_right(34) ^ x.rotate_right(39) }
+// Σ{512}1(x) = ROTR¹⁴(x) ⊕ ROTR¹⁸(x) ⊕ ROTR⁴¹(x)
+#[inline(always)] fn s3(x: u64) -> u64 { x.rotate_right(14) ^ x.rotate_right(18) ^ x.rotate_right(41) }
+
+pub(crate) fn starts() -> SHA512Context {
+    let state = [
+        0x6A09E667F3BCC908,
+        0xBB67AE8584CAA73B,
+        0x3C6EF372FE94F82B,
+        0xA54FF53A5F1D36F1,
+        0x510E527FADE682D1,
+        0x9B05688C2B3E6C1F,
+        0x1F83D9ABFB41BD6B,
+        0x5BE0CD19137E2179,
+    ];
+
+    SHA512Context {
+        total: [0u64; 2],
+        state,
+        buffer: [0u8; 128],
+        ipad: [0u8; 128],
+        opad: [0u8; 128],
+    }
+}
+
+pub(crate) fn process(state: &mut [u64; 8], data: &[u8]) {
+    assert!(data.len() == 128, "invalid data length");
+    let mut w: [u64; 80] = [0; 80];
+
+    for t in 0..16 {
+        w[t] = get_u64(data, t * 8);
+    }
+
+    for t in 16..80 {
+        w[t] = s1(w[t - 2]).wrapping_add(w[t - 7]).wrapping_add(s0(w[t - 15])).wrapping_add(w[t - 16]);
+    }
+
+    let mut a = state[0];
+    let mut b = state[1];
+    let mut c = state[2];
+    let mut d = state[3];
+    let mut e = state[4];
+    let mut f = state[5];
+    let mut g = state[6];
+    let mut h = state[7];
+
+    for t in 0..80 {
+        let temp1 = h.wrapping_add(s3(e)).wrapping_add((e & f) ^ (!e & g)).wrapping_add(K[t]).wrapping_add(w[t]);
+        let temp2 = s2(a).wrapping_add((a & b) ^ (a & c) ^ (b & c));
+
+        h = g;
+        g = f;
+        f = e;
+        e = d.wrapping_add(temp1);
+        d = c;
+        c = b;
+        b = a;
+        a = temp1.wrapping_add(temp2);
+    }
+
+    state[0] = state[0].wrapping_add(a);
+    state[1] = state[1].wrapping_add(b);
+    state[2] = state[2].wrapping_add(c);
+    state[3] = state[3].wrapping_add(d);
+    state[4] = state[4].wrapping_add(e);
+    state[5] = state[5].wrapping_add(f);
+    state[6] = state[6].wrapping_add(g);
+    state[7] = state[7].wrapping_add(h);
+}
+
+pub(crate) fn update(context: &mut SHA512Context, mut input: &[u8]) {
+    if input.is_empty() { return; }
+    let left = (context.total[0] & 0x7F) as usize;
+    let fill = 128 - left;
+
+    context.total[0] = context.total[0].wrapping_add(input.len() as u64);
+
+    if context.total[0] < input.len() as u64 {
+        context.total[1] += 1;
+    }
+
+    if left != 0 && input.len() >= fill {
+        context.buffer[left..].copy_from_slice(&input[..fill]);
+        process(&mut context.state, &context.buffer);
+        input = &input[fill..];
+    } else if left != 0 {
+        context.buffer[left..left + input.len()].copy_from_slice(input);
+        return;
+    }
+
+    while input.len() >= 128 {
+        process(&mut context.state, &input[..128]);
+        input = &input[128..];
+    }
+
+    context.buffer[..input.len()].copy_from_slice(input);
+}
+
+pub(crate) fn finish(context: &mut SHA512Context, digest: &mut [u8; 64]) {
+    let mut last = (context.total[0] & 0x7F) as usize;
+
+    context.buffer[last] = 0x80;
+    last += 1;
+
+    if last <= 112 {
+        // Enough room for padding + length in current block
+        memset(context.buffer[last..].as_mut_ptr(), 0, 112 - last);
+    } else {
+        // We'll need an extra block.
+        memset(context.buffer[last..].as_mut_ptr(), 0, 128 - last);
+
+        process(&mut context.state, &context.buffer);
+
+        memset(context.buffer.as_mut_ptr(), 0, 112);
+    }
+
+    let high: u64 = (context.total[0] >> 61) | (context.total[1] << 3);
+    let low:  u64 =  context.total[0] << 3;
+
+    put_u64(high, &mut context.buffer, 112);
+    put_u64(low , &mut context.buffer, 120);
+
+    process(&mut context.state, &context.buffer);
+
+    for i in 0..8 {
+        put_u64(context.state[i], digest, i * 8);
+    }
+}
+
+// SHA-512 HMAC context setup
+
+pub(crate) fn hmac_starts(key: &[u8]) -> SHA512Context {
+    let mut sum = [0u8; 64];
+
+    let key = if key.len() > 128 {
+        let mut ctx = starts();
+        update(&mut ctx, key);
+        finish(&mut ctx, &mut sum);
+        &sum[..]
+    } else {
+        key
+    };
+
+    let mut ctx = starts();
+
+    memset(ctx.ipad.as_mut_ptr(), 0x36, 128);
+    memset(ctx.opad.as_mut_ptr(), 0x5C, 128);
+
+    for i in 0..key.len() {
+        ctx.ipad[i] ^= key[i];
+        ctx.opad[i] ^= key[i];
+    }
+
+    let ipad = ctx.ipad;
+    update(&mut ctx, &ipad);
+
+    ctx
+}
+
+// SHA-512 HMAC process buffer
+
+pub(crate) fn hmac_update(context: &mut SHA512Context, input: &[u8]) {
+    update(context, input);
+}
+
+// SHA-512 HMAC final digest
+
+pub(crate) fn hmac_finish(context: &mut SHA512Context, digest: &mut [u8; 64]) {
+    let mut tmpbuf = [0u8; 64];
+
+    finish(context, &mut tmpbuf);
+
+    let opad = context.opad;
+    let mut outer = starts();
+
+    update(&mut outer, &opad);
+    update(&mut outer, &tmpbuf);
+
+    finish(&mut outer, digest);
+}
+
+#[test]
+fn one_block_message() {
+    let mut ctx = starts();
+    let mut digest = [0u8; 64];
+
+    update(&mut ctx, b"abc");
+    finish(&mut ctx, &mut digest);
+
+    assert_eq!(
+        <[u8]>::slice_to_hex(&digest),
+        "ddaf35a193617abacc417349ae20413112e6fa4e89a97ea20a9eeee64b55d39a\
+         2192992a274fc1a836ba3c23a3feebbd454d4423643ce80e2a9ac94fa54ca49f"
+    );
+}
+
+#[test]
+fn multi_block_message() {
+    let msg = "abcdefghbcdefghicdefghijdefghijkefghijklfghijklmghijklmn\
+               hijklmnoijklmnopjklmnopqklmnopqrlmnopqrsmnopqrstnopqrstu";
+    let mut ctx = starts();
+    let mut digest = [0u8; 64];
+
+    // fed in uneven pieces to exercise the buffer carry
+    for piece in msg.as_bytes().chunks(7) {
+        update(&mut ctx, piece);
+    }
+    finish(&mut ctx, &mut digest);
+
+    assert_eq!(
+        <[u8]>::slice_to_hex(&digest),
+        "8e959b75dae313da8cf4f72814fc143f8f7779c6eb9f7fa17299aeadb6889018\
+         501d289e4900f7e4331b99dec4b5433ac7d329eeb6dd26545e96e55b874be909"
+    );
+}
+
+#[test]
+fn hmac_rfc4231_case_2() {
+    let mut ctx = hmac_starts(b"Jefe");
+    let mut digest = [0u8; 64];
+
+    hmac_update(&mut ctx, b"what do ya want for nothing?");
+    hmac_finish(&mut ctx, &mut digest);
+
+    assert_eq!(
+        <[u8]>::slice_to_hex(&digest),
+        "164b7a7bfcf819e2e395fbe73b56e0a387bd64222e831fd610270cd7ea250554\
+         9758bf75c05a994a6d034f65f8f0e6fdcaeab1a34d4a6b4b636e070a38bce737"
+    );
+}
@@ -0,0 +1,60 @@
+// Helpers shared by the tests.
+
+use std::fs;
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+
+// contents of a file under tests/fixtures
+pub(crate) fn fixture(name: &str) -> Vec<u8> {
+    fs::read(format!("tests/fixtures/{}", name)).unwrap()
+}
+
+// A directory of its own under the system temp dir. It starts out empty
+// and is removed on drop, so nothing is left behind when a test panics.
+pub(crate) struct Scratch {
+    dir: PathBuf,
+}
+
+impl Scratch {
+    pub(crate) fn new(name: &str) -> Self {
+        let dir = std::env::temp_dir().join(format!("aescry-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        Scratch { dir }
+    }
+
+    // Writes `bytes` to `to` inside the directory, creating any parents,
+    // and returns the full path.
+    pub(crate) fn write(&self, to: &str, bytes: &[u8]) -> String {
+        let path = self.dir.join(to);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, bytes).unwrap();
+
+        path.to_str().unwrap().to_string()
+    }
+
+    pub(crate) fn copy_of(&self, name: &str, to: &str) -> String {
+        self.write(to, &fixture(name))
+    }
+}
+
+impl Deref for Scratch {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.dir
+    }
+}
+
+impl AsRef<Path> for Scratch {
+    fn as_ref(&self) -> &Path {
+        &self.dir
+    }
+}
+
+impl Drop for Scratch {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
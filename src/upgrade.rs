@@ -0,0 +1,128 @@
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+
+use crate::{detect, AesCryptReader, Encryptor};
+use crate::detect::ScanOptions;
+use crate::edit::replace_file;
+use crate::error::{Error, Result};
+#[cfg(test)]
+use crate::testing::{fixture, Scratch};
+
+// Re-encrypts a v0 or v1 file as version 2, with a CREATED_BY extension and
+// a fresh container. The new file is written next to the original and only
+// renamed over it once the old message HMAC has checked out. Returns false,
+// leaving the file alone, when it is already version 2 or later.
+pub fn upgrade(file: &str, password: &str) -> Result<bool> {
+    if detect::get_file(file)?.version >= 2 { return Ok(false) }
+
+    upgrade_with(Path::new(file), password, &Encryptor::new())?;
+
+    Ok(true)
+}
+
+fn upgrade_with(path: &Path, password: &str, encryptor: &Encryptor) -> Result<()> {
+    replace_file(path, |output| {
+        let mut reader = AesCryptReader::new(BufReader::new(File::open(path)?), password)?;
+        let mut writer = encryptor.writer(password, BufWriter::new(output))?;
+
+        io::copy(&mut reader, &mut writer)?;
+
+        Ok(writer.finish()?.into_inner().map_err(|e| e.into_error())?)
+    })
+}
+
+// `from_version` is None when the file could not be read or parsed; the
+// scan error is then carried in `result`.
+#[derive(Debug)]
+pub struct UpgradeEntry {
+    pub path: PathBuf,
+    pub from_version: Option<u8>,
+    pub result: Result<()>,
+}
+
+// Upgrades every v0/v1 file that `detect::scan_dir` finds under `root`.
+// Failures, including files the scan could not read, are reported per file
+// and do not stop the run. Files that are not AES Crypt are left out.
+pub fn upgrade_dir<P: AsRef<Path>>(root: P, options: &ScanOptions, password: &str) -> Result<Vec<UpgradeEntry>> {
+    let encryptor = Encryptor::new();
+    let mut upgraded = vec![];
+
+    for entry in detect::scan_dir(root, options)? {
+        let (from_version, result) = match entry.result {
+            Ok(ref file) if file.version >= 2 => continue,
+            Ok(ref file) => (Some(file.version), upgrade_with(&entry.path, password, &encryptor)),
+            Err(Error::NotAesCrypt) => continue,
+            Err(e) => (None, Err(e)),
+        };
+
+        upgraded.push(UpgradeEntry { path: entry.path, from_version, result });
+    }
+
+    Ok(upgraded)
+}
+
+#[test]
+fn upgrade_legacy_files() {
+    let dir = Scratch::new("upgrade-files");
+
+    for version in 0..2 {
+        let path = dir.copy_of(&format!("aligned_v{}.txt.aes", version), &format!("v{}.aes", version));
+
+        assert!(upgrade(&path, "pässwörd").unwrap());
+
+        let file = detect::get_file(&path).unwrap();
+        assert_eq!(file.version, 2);
+        assert_eq!(file.extensions()[0].identifier, "CREATED_BY");
+        assert_eq!(file.decrypt("pässwörd").unwrap().data, "0123456789abcdef".repeat(4).as_bytes());
+    }
+
+    let path = dir.copy_of("hello_v2.txt.aes", "v2.aes");
+    assert!(!upgrade(&path, "password").unwrap());
+    assert_eq!(fs::read(&path).unwrap(), fixture("hello_v2.txt.aes"));
+}
+
+#[test]
+fn upgrade_wrong_password() {
+    let dir = Scratch::new("upgrade-wrong");
+
+    // v0 only fails once the whole message has been through the HMAC
+    for version in 0..2 {
+        let name = format!("hello_v{}.txt.aes", version);
+        let path = dir.copy_of(&name, &name);
+
+        assert!(upgrade(&path, "wrong").is_err());
+        assert_eq!(fs::read(&path).unwrap(), fixture(&name));
+    }
+
+    // no temp files left behind
+    assert_eq!(fs::read_dir(&*dir).unwrap().count(), 2);
+}
+
+#[test]
+fn upgrade_scanned_dir() {
+    let dir = Scratch::new("upgrade-scan");
+
+    dir.copy_of("hello_v0.txt.aes", "v0.aes");
+    dir.copy_of("hello_v1.txt.aes", "nested/v1.aes");
+    dir.copy_of("hello_v3.txt.aes", "nested/v3.aes");
+    dir.copy_of("aligned_v1.txt.aes", "other.aes");
+    dir.write("notes.txt", b"plain");
+    dir.write("broken.aes", b"AES\x07\x00");
+
+    let mut entries = upgrade_dir(&dir, &ScanOptions::new(), "password").unwrap();
+    entries.sort_by(|a, b| a.path.cmp(&b.path));
+
+    let summary: Vec<_> = entries.iter().map(|e| (e.from_version, e.result.is_ok())).collect();
+    assert_eq!(summary, vec![(None, false), (Some(1), true), (Some(1), false), (Some(0), true)]);
+    assert!(matches!(entries[0].result, Err(Error::UnsupportedVersion(7))));
+
+    for name in &["v0.aes", "nested/v1.aes", "nested/v3.aes"] {
+        let file = detect::get_file(dir.join(name).to_str().unwrap()).unwrap();
+        assert!(file.version >= 2);
+        assert_eq!(file.decrypt("password").unwrap().data, b"Hello, AES Crypt!\n");
+    }
+
+    // encrypted under another password, so left as it was
+    assert_eq!(detect::get_file(dir.join("other.aes").to_str().unwrap()).unwrap().version, 1);
+}
@@ -1,5 +1,6 @@
 use std::slice;
 use std::fmt::LowerHex;
+use std::io::{self, Read};
 use std::mem;
 
 pub fn memset(t: *mut u8, val: u8, qty: usize) {
@@ -13,7 +14,7 @@ pub trait SliceToHex<T: LowerHex> {
 
 impl<T> SliceToHex<T> for [T] where T: std::fmt::LowerHex {
     fn slice_to_hex(&self) -> String {
-        let mut hex_digest = String::with_capacity(self.len() * mem::size_of::<T>() * 2);
+        let mut hex_digest = String::with_capacity(std::mem::size_of_val(self) * 2);
         
         let format = String::with_capacity(10);
 
@@ -25,6 +26,20 @@ impl<T> SliceToHex<T> for [T] where T: std::fmt::LowerHex {
     }
 }
 
+// passes reads through, adding up the octets taken from `inner`
+pub(crate) struct Counted<'a, R: Read> {
+    pub(crate) inner: R,
+    pub(crate) count: &'a mut u64,
+}
+
+impl<'a, R: Read> Read for Counted<'a, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        *self.count += n as u64;
+        Ok(n)
+    }
+}
+
 #[test]
 fn test_slice_to_hex() {
     use crate::util::SliceToHex;
@@ -0,0 +1,268 @@
+use std::io::{Cursor, Read, Seek, SeekFrom};
+
+use byteorder::{BigEndian, ByteOrder};
+
+use crate::decrypt::{read_exact, MAX_KDF_ITERATIONS};
+use crate::error::Result;
+use crate::header::{key_section_size, trailer_size};
+#[cfg(test)]
+use crate::testing::fixture;
+
+// How likely it is that a file really is AES Crypt output, judged from
+// its structure alone.
+//
+//  Rejected - no 'AES' signature or an unknown version
+//  Low      - signature present but the structure is inconsistent
+//  Medium   - consistent v0/v1 file; these carry little to check beyond
+//             the footprint and block alignment
+//  High     - consistent v2/v3 file, extension block included
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Confidence {
+    Rejected,
+    Low,
+    Medium,
+    High,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ValidationIssue {
+    NotAesCrypt,
+    UnsupportedVersion(u8),
+    TooSmall { minimum: u64, actual: u64 },
+    ReservedByte(u8),
+    // the extension's length field is at `offset`
+    UnterminatedIdentifier { offset: u64 },
+    ExtensionOverrun { offset: u64 },
+    InvalidKdfIterations(u32),
+    UnalignedCiphertext(u64),
+    ModuloOutOfRange(u8),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Validation {
+    pub version: Option<u8>,
+    pub confidence: Confidence,
+    pub issues: Vec<ValidationIssue>,
+}
+
+impl Validation {
+    // Checks the structure from the current position of `reader` to its
+    // end. Only read errors are returned as `Err`; structural problems are
+    // listed in `issues`.
+    pub fn from_reader<R: Read + Seek>(mut reader: R) -> Result<Self> {
+        let base = reader.stream_position()?;
+        let size = reader.seek(SeekFrom::End(0))? - base;
+        reader.seek(SeekFrom::Start(base))?;
+
+        let mut header = [0u8; 5];
+        let available = size.min(5) as usize;
+        read_exact(&mut reader, &mut header[..available])?;
+
+        if available < 4 || &header[..3] != b"AES" {
+            return Ok(rejected(None, ValidationIssue::NotAesCrypt))
+        }
+
+        let version = header[3];
+
+        // documented minimum footprint of each version
+        let mut minimum = match version {
+            0 => 53,
+            1 | 2 => 134,
+            3 => 155,
+            v => return Ok(rejected(Some(v), ValidationIssue::UnsupportedVersion(v))),
+        };
+
+        let mut issues = vec![];
+
+        if version >= 1 && available == 5 && header[4] != 0x00 {
+            issues.push(ValidationIssue::ReservedByte(header[4]));
+        }
+
+        if version == 0 && available == 5 && header[4] > 15 {
+            issues.push(ValidationIssue::ModuloOutOfRange(header[4]));
+        }
+
+        let mut offset = 5;
+        let mut complete = true;
+
+        if version >= 2 {
+            complete = check_extensions(&mut reader, size, &mut offset, &mut issues)?;
+        }
+
+        if complete && version == 3 {
+            if offset + 4 <= size {
+                let mut count = [0u8; 4];
+                read_exact(&mut reader, &mut count)?;
+
+                match BigEndian::read_u32(&count) {
+                    n if n == 0 || n > MAX_KDF_ITERATIONS => issues.push(ValidationIssue::InvalidKdfIterations(n)),
+                    _ => (),
+                }
+            }
+
+            offset += 4;
+        }
+
+        if complete {
+            let keys = key_section_size(version);
+            let trailer = trailer_size(version);
+
+            // PKCS#7 always leaves at least one block in version 3
+            let least = if version == 3 { 16 } else { 0 };
+
+            minimum = minimum.max(offset + keys + least + trailer);
+
+            if size >= minimum {
+                let ciphertext = size - trailer - (offset + keys);
+
+                if !ciphertext.is_multiple_of(16) {
+                    issues.push(ValidationIssue::UnalignedCiphertext(ciphertext));
+                }
+
+                if version == 1 || version == 2 {
+                    let mut modulo = [0u8; 1];
+                    reader.seek(SeekFrom::Start(base + size - trailer))?;
+                    read_exact(&mut reader, &mut modulo)?;
+
+                    if modulo[0] > 15 { issues.push(ValidationIssue::ModuloOutOfRange(modulo[0])) }
+                }
+            }
+        }
+
+        if size < minimum {
+            issues.push(ValidationIssue::TooSmall { minimum, actual: size });
+        }
+
+        let confidence = match (issues.is_empty(), version) {
+            (false, _) => Confidence::Low,
+            (true, 0) | (true, 1) => Confidence::Medium,
+            (true, _) => Confidence::High,
+        };
+
+        Ok(Validation { version: Some(version), confidence, issues })
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        Self::from_reader(Cursor::new(bytes))
+    }
+
+    pub fn is_valid(&self) -> bool {
+        self.issues.is_empty()
+    }
+}
+
+fn rejected(version: Option<u8>, issue: ValidationIssue) -> Validation {
+    Validation { version, confidence: Confidence::Rejected, issues: vec![issue] }
+}
+
+// Walks the extension block, advancing `offset` past the terminator.
+// Returns false when the block runs past the end of the file, in which
+// case nothing after it can be located.
+
+fn check_extensions<R: Read>(reader: &mut R, size: u64, offset: &mut u64, issues: &mut Vec<ValidationIssue>) -> Result<bool> {
+    loop {
+        if *offset + 2 > size {
+            issues.push(ValidationIssue::ExtensionOverrun { offset: *offset });
+            return Ok(false)
+        }
+
+        let mut ext_len = [0u8; 2];
+        read_exact(reader, &mut ext_len)?;
+
+        let length = BigEndian::read_u16(&ext_len) as u64;
+
+        if length == 0 {
+            *offset += 2;
+            return Ok(true)
+        }
+
+        if *offset + 2 + length > size {
+            issues.push(ValidationIssue::ExtensionOverrun { offset: *offset });
+            return Ok(false)
+        }
+
+        let mut contents = vec![0u8; length as usize];
+        read_exact(reader, &mut contents)?;
+
+        // containers are all zero; anything else needs a terminated identifier
+        if !contents.iter().all(|b| *b == 0x00) && !contents.contains(&0x00) {
+            issues.push(ValidationIssue::UnterminatedIdentifier { offset: *offset });
+        }
+
+        *offset += 2 + length;
+    }
+}
+
+#[test]
+fn fixtures_are_valid() {
+    for prefix in &["hello", "aligned"] {
+        for version in 0..4 {
+            let bytes = fixture(&format!("{}_v{}.txt.aes", prefix, version));
+            let validation = Validation::from_bytes(&bytes).unwrap();
+
+            assert!(validation.is_valid(), "{:?}", validation.issues);
+            assert_eq!(validation.version, Some(version));
+            assert_eq!(validation.confidence, if version < 2 { Confidence::Medium } else { Confidence::High });
+        }
+    }
+}
+
+#[test]
+fn plain_text_with_signature() {
+    let validation = Validation::from_bytes(b"AES\x00 is a block cipher.\n").unwrap();
+
+    assert_eq!(validation.confidence, Confidence::Low);
+    // the space after the signature is read as the v0 modulo octet
+    assert_eq!(validation.issues, vec![
+        ValidationIssue::ModuloOutOfRange(b' '),
+        ValidationIssue::TooSmall { minimum: 53, actual: 24 },
+    ]);
+
+    let validation = Validation::from_bytes(b"plain text").unwrap();
+    assert_eq!(validation.confidence, Confidence::Rejected);
+    assert_eq!(validation.issues, vec![ValidationIssue::NotAesCrypt]);
+
+    let validation = Validation::from_bytes(b"AES\x09\x00").unwrap();
+    assert_eq!(validation.confidence, Confidence::Rejected);
+}
+
+#[test]
+fn reserved_byte_and_alignment() {
+    let mut bytes = fixture("hello_v1.txt.aes");
+    bytes[4] = 0x01;
+    bytes.insert(110, 0x00);
+
+    let validation = Validation::from_bytes(&bytes).unwrap();
+
+    assert_eq!(validation.confidence, Confidence::Low);
+    assert_eq!(validation.issues, vec![ValidationIssue::ReservedByte(1), ValidationIssue::UnalignedCiphertext(33)]);
+}
+
+#[test]
+fn extension_problems() {
+    let mut bytes = fixture("hello_v2.txt.aes");
+    // "CREATED_BY\0..." loses its terminator
+    bytes[17] = b'_';
+
+    let validation = Validation::from_bytes(&bytes).unwrap();
+    assert_eq!(validation.issues, vec![ValidationIssue::UnterminatedIdentifier { offset: 5 }]);
+
+    let mut bytes = fixture("hello_v2.txt.aes");
+    // container length pointing past the end of the file
+    bytes[31] = 0xff;
+
+    let validation = Validation::from_bytes(&bytes).unwrap();
+    assert_eq!(validation.issues[0], ValidationIssue::ExtensionOverrun { offset: 31 });
+    assert_eq!(validation.confidence, Confidence::Low);
+}
+
+#[test]
+fn modulo_out_of_range() {
+    let mut bytes = fixture("hello_v2.txt.aes");
+    let at = bytes.len() - 33;
+    bytes[at] = 0x20;
+
+    let validation = Validation::from_bytes(&bytes).unwrap();
+    assert_eq!(validation.issues, vec![ValidationIssue::ModuloOutOfRange(0x20)]);
+}
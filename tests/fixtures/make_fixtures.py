@@ -0,0 +1,120 @@
+#!/usr/bin/env python3
+# Writes the AES Crypt files in this directory. The format is implemented
+# here independently of aescry, on top of hashlib/hmac and the AES-CBC of the
+# `cryptography` package (OpenSSL). IVs and session keys are derived from the
+# file name, so running it again reproduces the files byte for byte:
+#
+#     python3 tests/fixtures/make_fixtures.py && git diff --exit-code tests/fixtures
+#
+# These are not output of the AES Crypt tools; CREATED_BY names this script.
+
+import hashlib
+import hmac
+import os
+import struct
+
+from cryptography.hazmat.primitives.ciphers import Cipher, algorithms, modes
+
+HERE = os.path.dirname(os.path.abspath(__file__))
+
+CREATED_BY = b'make_fixtures'
+
+HELLO = (b'Hello, AES Crypt!\n', 'password')
+ALIGNED = (b'0123456789abcdef' * 4, 'pässwörd')
+
+
+def fixed(name, label, size):
+    return hashlib.sha256((name + ':' + label).encode()).digest()[:size]
+
+
+def cbc(key, iv, data):
+    e = Cipher(algorithms.AES(key), modes.CBC(iv)).encryptor()
+    return e.update(data) + e.finalize()
+
+
+def mac(key, data):
+    return hmac.new(key, data, 'sha256').digest()
+
+
+# 8192 rounds of SHA-256 over the IV and the UTF-16LE password
+def stretch(password, iv):
+    p = password.encode('utf-16-le')
+    d = iv + bytes(16)
+    for _ in range(8192):
+        d = hashlib.sha256(d + p).digest()
+    return d
+
+
+def zero_pad(data):
+    n = len(data) % 16
+    return data + bytes((16 - n) % 16), n
+
+
+def extension(identifier, contents):
+    body = identifier + b'\x00' + contents
+    return struct.pack('>H', len(body)) + body
+
+
+def extensions():
+    return (extension(b'CREATED_BY', CREATED_BY)
+            + struct.pack('>H', 128) + bytes(128)
+            + b'\x00\x00')
+
+
+def session(name, key, suffix=b''):
+    iv1 = fixed(name, 'iv1', 16)
+    iv2 = fixed(name, 'iv2', 16)
+    key2 = fixed(name, 'key2', 32)
+
+    block = cbc(key, iv1, iv2 + key2)
+    return iv1 + block + mac(key, block + suffix), iv2, key2
+
+
+def v0(name, data, password):
+    iv = fixed(name, 'iv1', 16)
+    key = stretch(password, iv)
+
+    body, modulo = zero_pad(data)
+    ct = cbc(key, iv, body)
+
+    return b'AES\x00' + bytes([modulo]) + iv + ct + mac(key, ct)
+
+
+def v1_v2(version, name, data, password):
+    key = stretch(password, fixed(name, 'iv1', 16))
+    block, iv2, key2 = session(name, key)
+
+    body, modulo = zero_pad(data)
+    ct = cbc(key2, iv2, body)
+
+    header = b'AES' + bytes([version, 0])
+    if version == 2:
+        header += extensions()
+
+    return header + block + ct + bytes([modulo]) + mac(key2, ct)
+
+
+def v3(name, data, password, iterations=1000):
+    iv1 = fixed(name, 'iv1', 16)
+    key = hashlib.pbkdf2_hmac('sha512', password.encode(), iv1, iterations, 32)
+    block, iv2, key2 = session(name, key, b'\x03')
+
+    n = 16 - len(data) % 16
+    ct = cbc(key2, iv2, data + bytes([n]) * n)
+
+    return (b'AES\x03\x00' + extensions() + struct.pack('>I', iterations)
+            + block + ct + mac(key2, ct))
+
+
+WRITERS = {
+    0: v0,
+    1: lambda name, data, password: v1_v2(1, name, data, password),
+    2: lambda name, data, password: v1_v2(2, name, data, password),
+    3: v3,
+}
+
+for version, write in sorted(WRITERS.items()):
+    for stem, (data, password) in (('hello', HELLO), ('aligned', ALIGNED)):
+        name = '%s_v%d.txt.aes' % (stem, version)
+        with open(os.path.join(HERE, name), 'wb') as f:
+            f.write(write(name, data, password))